@@ -0,0 +1,212 @@
+use std::fmt::Debug;
+use miniquad::*;
+
+// the handle types a backend draws with. miniquad's can only be made
+// through a Context, so tests use Ids, plain numbers they pick themselves
+pub trait Handles {
+    type Pass: Copy + Debug + PartialEq;
+    type Pipeline: Copy + Debug;
+    type Bindings: Clone + Debug;
+    type Buffer: Copy + Debug;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gl;
+
+impl Handles for Gl {
+    type Pass = RenderPass;
+    type Pipeline = Pipeline;
+    type Bindings = Bindings;
+    type Buffer = Buffer;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ids;
+
+impl Handles for Ids {
+    type Pass = usize;
+    type Pipeline = usize;
+    type Bindings = usize;
+    type Buffer = usize;
+}
+
+// the per-frame drawing operations the pipes use, so passes can be
+// drawn into something other than a miniquad Context
+pub trait Backend<H: Handles = Gl> {
+    fn begin_pass(&mut self, pass: Option<H::Pass>, action: PassAction);
+    fn end_render_pass(&mut self);
+    fn apply_pipeline(&mut self, pipeline: &H::Pipeline);
+    fn apply_bindings(&mut self, bindings: &H::Bindings);
+    fn apply_uniforms_from_bytes(&mut self, data: &[u8]);
+    fn update_buffer(&mut self, buffer: &H::Buffer, data: &[u8]);
+    fn draw(&mut self, base_element: i32, num_elements: i32, num_instances: i32);
+}
+
+/// plain data whose bytes can be read and written freely
+///
+/// # Safety
+///
+/// the type must have no padding, no pointers and no invalid bit patterns:
+/// a #[repr(C)] struct of f32s and glam vectors and matrices whose fields
+/// leave no gaps
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for () {}
+unsafe impl Pod for f32 {}
+
+// the raw bytes of uniform blocks, vertices and instances
+pub fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(
+            data.as_ptr() as *const u8,
+            std::mem::size_of_val(data),
+        )
+    }
+}
+
+impl<H: Handles> dyn Backend<H> + '_ {
+    pub fn begin_default_pass(&mut self, action: PassAction) {
+        self.begin_pass(None, action);
+    }
+
+    pub fn apply_uniforms<U: Pod>(&mut self, uniforms: &U) {
+        self.apply_uniforms_from_bytes(as_bytes(std::slice::from_ref(uniforms)));
+    }
+}
+
+impl Backend for Context {
+    fn begin_pass(&mut self, pass: Option<RenderPass>, action: PassAction) {
+        Context::begin_pass(self, pass, action);
+    }
+
+    fn end_render_pass(&mut self) {
+        Context::end_render_pass(self);
+    }
+
+    fn apply_pipeline(&mut self, pipeline: &Pipeline) {
+        Context::apply_pipeline(self, pipeline);
+    }
+
+    fn apply_bindings(&mut self, bindings: &Bindings) {
+        Context::apply_bindings(self, bindings);
+    }
+
+    fn apply_uniforms_from_bytes(&mut self, data: &[u8]) {
+        Context::apply_uniforms_from_bytes(self, data.as_ptr(), data.len());
+    }
+
+    fn update_buffer(&mut self, buffer: &Buffer, data: &[u8]) {
+        buffer.update(self, data);
+    }
+
+    fn draw(&mut self, base_element: i32, num_elements: i32, num_instances: i32) {
+        Context::draw(self, base_element, num_elements, num_instances);
+    }
+}
+
+// one draw call along with the state it was made in
+#[derive(Clone, Debug)]
+pub struct DrawCall<H: Handles = Gl> {
+    pub pass: Option<H::Pass>,
+    pub pipeline: Option<H::Pipeline>,
+    pub bindings: Option<H::Bindings>,
+    pub uniforms: Vec<u8>,
+    pub base_element: i32,
+    pub num_elements: i32,
+    pub num_instances: i32,
+}
+
+impl<H: Handles> DrawCall<H> {
+    // the uniform payload as the struct it was applied from
+    pub fn uniforms_as<U: Pod>(&self) -> U {
+        assert_eq!(self.uniforms.len(), std::mem::size_of::<U>(),
+            "uniform payload is not the size of the requested type");
+        unsafe { std::ptr::read_unaligned(self.uniforms.as_ptr() as *const U) }
+    }
+}
+
+// a backend that draws nothing and logs what it was asked to do
+pub struct Recorder<H: Handles = Gl> {
+    // every pass begun, None for the screen
+    pub passes: Vec<Option<H::Pass>>,
+    pub draws: Vec<DrawCall<H>>,
+    // contents of every buffer update, in order
+    pub uploads: Vec<Vec<u8>>,
+    pass: Option<H::Pass>,
+    pipeline: Option<H::Pipeline>,
+    bindings: Option<H::Bindings>,
+    uniforms: Vec<u8>,
+    in_pass: bool,
+}
+
+impl<H: Handles> Recorder<H> {
+    pub fn new() -> Recorder<H> {
+        Recorder {
+            passes: vec![],
+            draws: vec![],
+            uploads: vec![],
+            pass: None,
+            pipeline: None,
+            bindings: None,
+            uniforms: vec![],
+            in_pass: false,
+        }
+    }
+
+    // draws made while the given pass was bound
+    pub fn draws_in(&self, pass: Option<H::Pass>) -> Vec<&DrawCall<H>> {
+        self.draws.iter().filter(|d| d.pass == pass).collect()
+    }
+}
+
+impl<H: Handles> Default for Recorder<H> {
+    fn default() -> Recorder<H> {
+        Recorder::new()
+    }
+}
+
+impl<H: Handles> Backend<H> for Recorder<H> {
+    fn begin_pass(&mut self, pass: Option<H::Pass>, _action: PassAction) {
+        assert!(!self.in_pass, "begin_pass inside another pass");
+        self.in_pass = true;
+        self.pass = pass;
+        self.passes.push(pass);
+    }
+
+    fn end_render_pass(&mut self) {
+        assert!(self.in_pass, "end_render_pass without begin_pass");
+        self.in_pass = false;
+        self.pipeline = None;
+        self.bindings = None;
+        self.uniforms.clear();
+    }
+
+    fn apply_pipeline(&mut self, pipeline: &H::Pipeline) {
+        self.pipeline = Some(*pipeline);
+    }
+
+    fn apply_bindings(&mut self, bindings: &H::Bindings) {
+        self.bindings = Some(bindings.clone());
+    }
+
+    fn apply_uniforms_from_bytes(&mut self, data: &[u8]) {
+        self.uniforms = data.to_vec();
+    }
+
+    fn update_buffer(&mut self, _buffer: &H::Buffer, data: &[u8]) {
+        self.uploads.push(data.to_vec());
+    }
+
+    fn draw(&mut self, base_element: i32, num_elements: i32, num_instances: i32) {
+        assert!(self.in_pass, "draw outside of a pass");
+        self.draws.push(DrawCall {
+            pass: self.pass,
+            pipeline: self.pipeline,
+            bindings: self.bindings.clone(),
+            uniforms: self.uniforms.clone(),
+            base_element,
+            num_elements,
+            num_instances,
+        });
+    }
+}
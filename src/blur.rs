@@ -0,0 +1,113 @@
+use miniquad::*;
+use mq_test::backend::{Backend, Pod};
+use glam::vec2;
+use crate::post_node::{PostNode, Output};
+use crate::render_graph::{Frame, RenderNode};
+use crate::target::Size;
+
+// a separable blur: one pass across then one down, each a PostNode with
+// its own target
+
+// what to blur with. name prefixes the pass and texture names, and meta
+// must describe Uniforms
+pub struct BlurShaders {
+    pub name: &'static str,
+    pub horiz: &'static str,
+    pub vert: &'static str,
+    pub meta: ShaderMeta,
+}
+
+pub struct Blur {
+    horiz:PostNode<Uniforms>,
+    vert:PostNode<Uniforms>,
+    radius:f32,
+    output:Texture
+}
+
+impl Blur {
+    pub fn new(ctx: &mut Context, quad: &Bindings, size: Size, radius:f32,
+        input:Texture, shaders: BlurShaders) -> Blur {
+        let target = |name| Output::Texture {
+            name,
+            size,
+            format: TextureFormat::RGBA8,
+        };
+        let uniforms = Uniforms {
+            resolution: vec2(0.0, 0.0),
+            radius
+        };
+        // built once per blur, so leaking them is fine
+        let name = |pass: &str| -> &'static str {
+            Box::leak(format!("{}_{}", shaders.name, pass).into_boxed_str())
+        };
+        let (input_name, horiz_name, vert_name) = (name("input"), name("horiz"), name("vert"));
+        let horiz = PostNode::new(ctx, quad, shaders.horiz, shaders.meta.clone(),
+            vec![input_name], target(horiz_name), uniforms);
+        let vert = PostNode::new(ctx, quad, shaders.vert, shaders.meta,
+            vec![horiz_name], target(vert_name), uniforms);
+        let mut pipe = Blur {
+            horiz,
+            vert,
+            radius,
+            output: input
+        };
+        pipe.set_input(input);
+        pipe.connect();
+        pipe
+    }
+
+    fn connect(&mut self) {
+        // sample offsets are one texel of the target being drawn to
+        let (w, h) = self.horiz.size().unwrap();
+        self.horiz.set_uniforms(Uniforms {
+            resolution: vec2(1.0 / w as f32, 1.0 / h as f32),
+            radius: self.radius
+        });
+        let (w, h) = self.vert.size().unwrap();
+        self.vert.set_uniforms(Uniforms {
+            resolution: vec2(1.0 / w as f32, 1.0 / h as f32),
+            radius: self.radius
+        });
+        self.vert.set_input(0, self.horiz.get_output(0).unwrap());
+        self.output = self.vert.get_output(0).unwrap();
+    }
+
+    pub fn set_input(&mut self, input:Texture) {
+        self.horiz.set_input(0, input);
+    }
+
+    pub fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.horiz.resize(ctx, width, height);
+        self.vert.resize(ctx, width, height);
+        self.connect();
+    }
+
+    pub fn draw(&self, gfx: &mut dyn Backend, frame: &Frame) {
+        self.horiz.draw(gfx, frame);
+        self.vert.draw(gfx, frame);
+    }
+
+    pub fn get_output(&self) -> Texture {
+        self.output
+    }
+}
+
+pub fn meta() -> ShaderMeta {
+    ShaderMeta {
+        images: vec!["tex".to_string()],
+        uniforms: UniformBlockLayout {
+            uniforms: vec![
+                UniformDesc::new("resolution", UniformType::Float2),
+                UniformDesc::new("radius", UniformType::Float1),
+            ]},
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Uniforms {
+    pub resolution: glam::Vec2,
+    pub radius: f32
+}
+
+unsafe impl Pod for Uniforms {}
@@ -0,0 +1,156 @@
+// turns real elapsed time into a whole number of fixed simulation steps,
+// carrying the remainder over to the next frame. alpha() says how far
+// rendering is between the last two steps
+
+// never run more than this many steps in one frame, so a long stall
+// doesn't have to be caught up all at once
+const MAX_STEPS: u32 = 10;
+
+pub struct Clock {
+    // seconds of simulation per step
+    step: f64,
+    accumulator: f64,
+    last: Option<f64>,
+    paused: bool,
+    time_scale: f64,
+    pending_steps: u32,
+}
+
+impl Clock {
+    pub fn new(steps_per_second: f64) -> Clock {
+        Clock {
+            step: 1.0 / steps_per_second,
+            accumulator: 0.0,
+            last: None,
+            paused: false,
+            time_scale: 1.0,
+            pending_steps: 0,
+        }
+    }
+
+    // call once a frame with the current real time in seconds. returns the
+    // number of steps to simulate
+    pub fn tick(&mut self, now: f64) -> u32 {
+        let elapsed = match self.last {
+            Some(last) => (now - last).max(0.0),
+            None => 0.0,
+        };
+        self.last = Some(now);
+
+        if self.paused {
+            let steps = self.pending_steps;
+            self.pending_steps = 0;
+            return steps;
+        }
+
+        self.accumulator += elapsed * self.time_scale;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < MAX_STEPS {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps == MAX_STEPS {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+        steps
+    }
+
+    // seconds of simulation time per step
+    pub fn dt(&self) -> f32 {
+        self.step as f32
+    }
+
+    // how far between the previous and latest step to draw, 0..1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).min(1.0) as f32
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // any part step is dropped either way, so alpha() stays at 0 while
+    // paused and running restarts on a whole step
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.pending_steps = 0;
+        self.accumulator = 0.0;
+    }
+
+    // 1.0 is real time, less is slow motion
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    // while paused, run one step on the next tick
+    pub fn single_step(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+            self.accumulator = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // quarter second steps, which add up exactly in binary
+    fn started() -> Clock {
+        let mut clock = Clock::new(4.0);
+        assert_eq!(clock.tick(0.0), 0);
+        clock
+    }
+
+    #[test]
+    fn whole_steps_are_run_and_the_rest_carried() {
+        let mut clock = started();
+        assert_eq!(clock.tick(0.625), 2);
+        assert_eq!(clock.alpha(), 0.5);
+        assert_eq!(clock.tick(0.75), 1);
+        assert_eq!(clock.alpha(), 0.0);
+        assert_eq!(clock.tick(0.875), 0);
+        assert_eq!(clock.alpha(), 0.5);
+
+        clock.set_time_scale(0.5);
+        assert_eq!(clock.tick(1.25), 1);
+    }
+
+    #[test]
+    fn a_long_stall_runs_at_most_max_steps() {
+        let mut clock = started();
+        assert_eq!(clock.tick(100.0), MAX_STEPS);
+        assert_eq!(clock.alpha(), 1.0);
+        // the rest of the stall is dropped
+        assert_eq!(clock.tick(100.125), 1);
+        assert_eq!(clock.alpha(), 0.5);
+    }
+
+    #[test]
+    fn pausing_stops_steps_until_single_stepped() {
+        let mut clock = started();
+        assert_eq!(clock.tick(0.375), 1);
+        assert_eq!(clock.alpha(), 0.5);
+
+        clock.set_paused(true);
+        assert_eq!(clock.alpha(), 0.0);
+        assert_eq!(clock.tick(10.0), 0);
+        clock.single_step();
+        clock.single_step();
+        assert_eq!(clock.tick(11.0), 2);
+        assert_eq!(clock.alpha(), 0.0);
+        assert_eq!(clock.tick(12.0), 0);
+
+        // time spent paused isn't caught up
+        clock.set_paused(false);
+        assert_eq!(clock.tick(12.375), 1);
+        assert_eq!(clock.alpha(), 0.5);
+        // and single steps add nothing while running
+        clock.single_step();
+        assert_eq!(clock.tick(12.5), 1);
+    }
+}
@@ -0,0 +1,335 @@
+use glam::{Vec3, Mat4};
+use crate::culling::Aabb;
+use crate::objects::{Object, ChunkManager};
+use crate::meshes::MeshRegistry;
+
+// oriented boxes around objects, a sphere that can be moved among them
+// sliding along whatever it touches, and rays cast at them
+
+#[derive(Clone, Copy, Debug)]
+pub struct Obb {
+    pub centre: Vec3,
+    // unit axes, and the half size along each
+    pub axes: [Vec3; 3],
+    pub half: Vec3,
+}
+
+impl Obb {
+    // the object's mesh bounds carried through its model matrix. fits
+    // exactly while the model is rotation and scale with no shear, as the
+    // generated objects are
+    pub fn from_object(meshes: &MeshRegistry, obj: &Object) -> Obb {
+        Obb::new(&meshes.get(obj.mesh).bounds, obj.model)
+    }
+
+    pub fn new(bounds: &Aabb, model: Mat4) -> Obb {
+        let cols = [model.x_axis.truncate(), model.y_axis.truncate(), model.z_axis.truncate()];
+        let lengths = Vec3::new(cols[0].length(), cols[1].length(), cols[2].length());
+        Obb {
+            centre: model.transform_point3((bounds.min + bounds.max) * 0.5),
+            axes: [cols[0] / lengths.x, cols[1] / lengths.y, cols[2] / lengths.z],
+            half: (bounds.max - bounds.min) * 0.5 * lengths,
+        }
+    }
+
+    // the point in or on the box nearest to p
+    pub fn closest_point(&self, p: Vec3) -> Vec3 {
+        let d = p - self.centre;
+        let mut q = self.centre;
+        for i in 0..3 {
+            let t = d.dot(self.axes[i]).clamp(-self.half[i], self.half[i]);
+            q += self.axes[i] * t;
+        }
+        q
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sphere {
+    pub centre: Vec3,
+    pub radius: f32,
+}
+
+// how to separate two overlapping shapes: move the first depth along normal
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub normal: Vec3,
+    pub depth: f32,
+}
+
+pub fn sphere_obb(sphere: &Sphere, obb: &Obb) -> Option<Contact> {
+    let q = obb.closest_point(sphere.centre);
+    let d = sphere.centre - q;
+    let dist = d.length();
+    if dist >= sphere.radius {
+        return None;
+    }
+    if dist > 0.0 {
+        return Some(Contact { normal: d / dist, depth: sphere.radius - dist });
+    }
+
+    // the centre is inside, so leave by the nearest face
+    let local = sphere.centre - obb.centre;
+    let mut best = Contact { normal: Vec3::ZERO, depth: f32::MAX };
+    for i in 0..3 {
+        let t = local.dot(obb.axes[i]);
+        let depth = obb.half[i] - t.abs() + sphere.radius;
+        if depth < best.depth {
+            let side = if t < 0.0 { -1.0 } else { 1.0 };
+            best = Contact { normal: obb.axes[i] * side, depth };
+        }
+    }
+    Some(best)
+}
+
+// passes of pushing out of contacts after a move. more handles corners
+// between several boxes better
+const SLIDE_ITERATIONS: usize = 4;
+
+// where a sphere ends up moved by delta through the boxes. anything it
+// runs into pushes it back out along the contact normal, which keeps the
+// part of the move along the surface so it slides rather than stopping
+pub fn slide(obbs: &[Obb], sphere: &Sphere, delta: Vec3) -> Vec3 {
+    let mut moved = Sphere {
+        centre: sphere.centre + delta,
+        radius: sphere.radius,
+    };
+    for _ in 0..SLIDE_ITERATIONS {
+        let mut touching = false;
+        for obb in obbs.iter() {
+            if let Some(contact) = sphere_obb(&moved, obb) {
+                moved.centre += contact.normal * contact.depth;
+                touching = true;
+            }
+        }
+        if !touching {
+            break;
+        }
+    }
+    moved.centre
+}
+
+// boxes for the objects, plain and coloured, in loaded chunks and in others
+// that come within reach of centre
+pub fn obstacles(chunks: &ChunkManager, others: &[Object], meshes: &MeshRegistry,
+    centre: Vec3, reach: f32) -> Vec<Obb> {
+    let area = Aabb::new(centre - Vec3::splat(reach), centre + Vec3::splat(reach));
+    let overlaps = |b: &Aabb| b.min.cmple(area.max).all() && b.max.cmpge(area.min).all();
+    let mut obbs = Vec::<Obb>::new();
+    for chunk in chunks.chunks() {
+        if !overlaps(&chunk.bounds) {
+            continue;
+        }
+        let coloured = chunk.coloured_objects.iter().map(|c| &c.object);
+        for obj in chunk.objects.iter().chain(coloured) {
+            if overlaps(&meshes.bounds(obj)) {
+                obbs.push(Obb::from_object(meshes, obj));
+            }
+        }
+    }
+    for obj in others.iter() {
+        if overlaps(&meshes.bounds(obj)) {
+            obbs.push(Obb::from_object(meshes, obj));
+        }
+    }
+    obbs
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    // unit length
+    pub dir: Vec3,
+}
+
+impl Ray {
+    // the ray through pixel x, y of a width by height screen, in the space
+    // view_proj transforms from
+    pub fn from_screen(x: f32, y: f32, width: f32, height: f32, view_proj: Mat4) -> Ray {
+        let (nx, ny) = (2.0 * x / width - 1.0, 1.0 - 2.0 * y / height);
+        let inv = view_proj.inverse();
+        let near = inv.project_point3(Vec3::new(nx, ny, -1.0));
+        let far = inv.project_point3(Vec3::new(nx, ny, 1.0));
+        Ray {
+            origin: near,
+            dir: (far - near).normalize(),
+        }
+    }
+}
+
+// distance along the ray to where it enters the box, or zero if it starts
+// inside
+pub fn ray_obb(ray: &Ray, obb: &Obb) -> Option<f32> {
+    let p = obb.centre - ray.origin;
+    let (mut near, mut far) = (0.0f32, f32::MAX);
+    for i in 0..3 {
+        let e = obb.axes[i].dot(p);
+        let f = obb.axes[i].dot(ray.dir);
+        if f.abs() > 1e-6 {
+            let t1 = (e - obb.half[i]) / f;
+            let t2 = (e + obb.half[i]) / f;
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+            if near > far {
+                return None;
+            }
+        } else if e.abs() > obb.half[i] {
+            // parallel to this pair of faces and outside them
+            return None;
+        }
+    }
+    Some(near)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub index: usize,
+    pub distance: f32,
+}
+
+// the nearest of objects the ray hits
+pub fn pick<'a>(ray: &Ray, objects: impl Iterator<Item = &'a Object>,
+    meshes: &MeshRegistry) -> Option<Hit> {
+    let mut nearest: Option<Hit> = None;
+    for (index, obj) in objects.enumerate() {
+        if let Some(distance) = ray_obb(ray, &Obb::from_object(meshes, obj)) {
+            match nearest {
+                Some(h) if h.distance <= distance => {}
+                _ => nearest = Some(Hit { index, distance }),
+            }
+        }
+    }
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec3;
+    use crate::meshes;
+
+    fn cube(model: Mat4) -> Object {
+        Object { model, mesh: meshes::builtin("cube").unwrap() }
+    }
+
+    fn unit_box(centre: Vec3) -> Obb {
+        Obb { centre, axes: [Vec3::X, Vec3::Y, Vec3::Z], half: Vec3::ONE }
+    }
+
+    // looking down -z from z 5 at the origin
+    fn view_proj() -> Mat4 {
+        Mat4::perspective_rh_gl(60f32.to_radians(), 2.0, 0.1, 100.0)
+            * Mat4::look_at_rh(vec3(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y)
+    }
+
+    fn near(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn rays_from_the_screen_centre_look_ahead() {
+        let ray = Ray::from_screen(400.0, 200.0, 800.0, 400.0, view_proj());
+        assert!((ray.origin - vec3(0.0, 0.0, 4.9)).length() < 1e-4);
+        assert!((ray.dir - vec3(0.0, 0.0, -1.0)).length() < 1e-4);
+        // the top left corner is up and to the left
+        let corner = Ray::from_screen(0.0, 0.0, 800.0, 400.0, view_proj());
+        assert!(corner.dir.x < 0.0 && corner.dir.y > 0.0 && corner.dir.z < 0.0);
+    }
+
+    #[test]
+    fn rays_hit_boxes_in_front() {
+        let ray = Ray::from_screen(400.0, 200.0, 800.0, 400.0, view_proj());
+        assert!(near(ray_obb(&ray, &unit_box(Vec3::ZERO)).unwrap(), 3.9));
+        // a turned box is hit on its corner edge, sqrt 2 out
+        let turned = Obb::from_object(&meshes::MeshRegistry::new(),
+            &cube(Mat4::from_rotation_y(45f32.to_radians())));
+        assert!(near(ray_obb(&ray, &turned).unwrap(), 4.9 - 2f32.sqrt()));
+    }
+
+    #[test]
+    fn rays_miss_boxes_beside_and_behind_them() {
+        let ray = Ray { origin: vec3(0.0, 0.0, 5.0), dir: vec3(0.0, 0.0, -1.0) };
+        assert!(ray_obb(&ray, &unit_box(vec3(3.0, 0.0, 0.0))).is_none());
+        assert!(ray_obb(&ray, &unit_box(vec3(0.0, 0.0, 8.0))).is_none());
+        let corner = Ray::from_screen(0.0, 0.0, 800.0, 400.0, view_proj());
+        assert!(ray_obb(&corner, &unit_box(Vec3::ZERO)).is_none());
+    }
+
+    #[test]
+    fn rays_starting_inside_hit_at_zero() {
+        let ray = Ray { origin: vec3(0.5, 0.0, 0.0), dir: vec3(0.0, 0.0, -1.0) };
+        assert_eq!(ray_obb(&ray, &unit_box(Vec3::ZERO)), Some(0.0));
+    }
+
+    #[test]
+    fn picks_the_nearest_object() {
+        let meshes = meshes::MeshRegistry::new();
+        let ray = Ray { origin: vec3(0.0, 0.0, 10.0), dir: vec3(0.0, 0.0, -1.0) };
+        let objects = [
+            cube(Mat4::IDENTITY),
+            cube(Mat4::from_translation(vec3(0.0, 0.0, 4.0))),
+            cube(Mat4::from_translation(vec3(5.0, 0.0, 8.0))),
+        ];
+        let hit = pick(&ray, objects.iter(), &meshes).unwrap();
+        assert_eq!(hit.index, 1);
+        assert!(near(hit.distance, 5.0));
+        let hit = pick(&ray, objects.iter().rev(), &meshes).unwrap();
+        assert_eq!(hit.index, 1);
+        assert!(pick(&ray, objects[2..].iter(), &meshes).is_none());
+    }
+
+    #[test]
+    fn spheres_pressed_into_a_turned_face_are_pushed_straight_out() {
+        let turn = Mat4::from_rotation_y(30f32.to_radians());
+        let obb = Obb::from_object(&meshes::MeshRegistry::new(), &cube(turn));
+        let out = turn.transform_vector3(Vec3::X);
+        let sphere = Sphere { centre: out * 1.3 + turn.transform_vector3(Vec3::Z) * 0.4,
+            radius: 0.5 };
+        let contact = sphere_obb(&sphere, &obb).unwrap();
+        assert!((contact.normal - out).length() < 1e-5);
+        assert!(near(contact.depth, 0.2));
+        // just clear of the face there's nothing to do
+        let clear = Sphere { centre: out * 1.6, radius: 0.5 };
+        assert!(sphere_obb(&clear, &obb).is_none());
+    }
+
+    #[test]
+    fn centres_inside_leave_by_the_nearest_face() {
+        let obb = unit_box(Vec3::ZERO);
+        let sphere = Sphere { centre: vec3(0.2, -0.8, 0.1), radius: 0.25 };
+        let contact = sphere_obb(&sphere, &obb).unwrap();
+        assert_eq!(contact.normal, -Vec3::Y);
+        assert!(near(contact.depth, 0.45));
+        let moved = slide(&[obb], &sphere, Vec3::ZERO);
+        assert!((moved - vec3(0.2, -1.25, 0.1)).length() < 1e-5);
+    }
+
+    #[test]
+    fn sliding_keeps_the_motion_along_the_face() {
+        // resting on top of the box and pushed down into it as it moves
+        let obb = unit_box(Vec3::ZERO);
+        let sphere = Sphere { centre: vec3(0.0, 1.5, 0.0), radius: 0.5 };
+        let moved = slide(&[obb], &sphere, vec3(0.4, -0.3, -0.2));
+        assert!((moved - vec3(0.4, 1.5, -0.2)).length() < 1e-5);
+        // and along a turned wall
+        let turn = Mat4::from_rotation_y(30f32.to_radians());
+        let wall = Obb::from_object(&meshes::MeshRegistry::new(), &cube(turn));
+        let (out, along) = (turn.transform_vector3(Vec3::X), turn.transform_vector3(Vec3::Z));
+        let sphere = Sphere { centre: out * 1.5, radius: 0.5 };
+        let moved = slide(&[wall], &sphere, along * 0.5 - out * 0.3);
+        assert!((moved - (out * 1.5 + along * 0.5)).length() < 1e-5);
+    }
+
+    #[test]
+    fn obstacles_are_the_objects_within_reach() {
+        let meshes = meshes::MeshRegistry::new();
+        let chunks = ChunkManager::new(1, &crate::rules::Rules::builtin());
+        let others = [
+            cube(Mat4::from_translation(vec3(2.0, 0.0, 0.0))),
+            cube(Mat4::from_translation(vec3(9.0, 0.0, 0.0))),
+        ];
+        let obbs = obstacles(&chunks, &others, &meshes, Vec3::ZERO, 1.5);
+        assert_eq!(obbs.len(), 1);
+        assert_eq!(obbs[0].centre, vec3(2.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,150 @@
+use glam::{vec3, Vec3, Vec4, Mat4};
+use crate::objects::{Object, ColouredObject, ChunkManager};
+use crate::meshes::MeshRegistry;
+use crate::terrain::TerrainPatch;
+
+// axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            vec3(a.x, a.y, a.z), vec3(b.x, a.y, a.z),
+            vec3(a.x, b.y, a.z), vec3(b.x, b.y, a.z),
+            vec3(a.x, a.y, b.z), vec3(b.x, a.y, b.z),
+            vec3(a.x, b.y, b.z), vec3(b.x, b.y, b.z),
+        ]
+    }
+
+    // the box around this one once transformed by m
+    pub fn transform(&self, m: Mat4) -> Aabb {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for c in self.corners().iter() {
+            let p = m.transform_point3(*c);
+            min = min.min(p);
+            max = max.max(p);
+        }
+        Aabb { min, max }
+    }
+}
+
+// the six clip planes of a projection, pointing inwards
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_matrix(m: Mat4) -> Frustum {
+        // Gribb & Hartmann plane extraction for a GL style -w..w clip space
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        Frustum {
+            planes: [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2],
+        }
+    }
+
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        for p in self.planes.iter() {
+            // the corner furthest along the plane normal
+            let v = vec3(
+                if p.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if p.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if p.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            if p.truncate().dot(v) + p.w < 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, meshes: &MeshRegistry, obj: &Object) -> bool {
+        self.intersects(&meshes.bounds(obj))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullStats {
+    pub visible: usize,
+    pub culled: usize,
+}
+
+// the objects at least partly inside the frustum of view_proj
+pub fn cull(objects: &[Object], meshes: &MeshRegistry, view_proj: Mat4,
+    stats: &mut CullStats) -> Vec<Object> {
+    let frustum = Frustum::from_matrix(view_proj);
+    let visible: Vec<Object> = objects.iter()
+        .filter(|obj| frustum.contains(meshes, obj))
+        .copied()
+        .collect();
+    stats.visible += visible.len();
+    stats.culled += objects.len() - visible.len();
+    visible
+}
+
+// the objects of every loaded chunk inside the frustum, testing the chunk
+// bounds first so chunks wholly outside are skipped in one go
+pub fn cull_chunks(chunks: &ChunkManager, meshes: &MeshRegistry, view_proj: Mat4,
+    stats: &mut CullStats) -> Vec<Object> {
+    let frustum = Frustum::from_matrix(view_proj);
+    let mut visible = Vec::<Object>::new();
+    for chunk in chunks.chunks() {
+        if !frustum.intersects(&chunk.bounds) {
+            stats.culled += chunk.objects.len();
+            continue;
+        }
+        visible.extend(cull(&chunk.objects, meshes, view_proj, stats));
+    }
+    visible
+}
+
+pub fn cull_chunks_coloured(chunks: &ChunkManager, meshes: &MeshRegistry,
+    view_proj: Mat4, stats: &mut CullStats) -> Vec<ColouredObject> {
+    let frustum = Frustum::from_matrix(view_proj);
+    let mut visible = Vec::<ColouredObject>::new();
+    for chunk in chunks.chunks() {
+        if !frustum.intersects(&chunk.bounds) {
+            stats.culled += chunk.coloured_objects.len();
+            continue;
+        }
+        visible.extend(cull_coloured(&chunk.coloured_objects, meshes, view_proj, stats));
+    }
+    visible
+}
+
+// the terrain patches of loaded chunks inside the frustum
+pub fn cull_terrain<'a>(chunks: &'a ChunkManager, view_proj: Mat4,
+    stats: &mut CullStats) -> Vec<&'a TerrainPatch> {
+    let frustum = Frustum::from_matrix(view_proj);
+    let mut visible = Vec::<&TerrainPatch>::new();
+    for chunk in chunks.chunks() {
+        if frustum.intersects(&chunk.terrain.bounds) {
+            visible.push(&chunk.terrain);
+            stats.visible += 1;
+        } else {
+            stats.culled += 1;
+        }
+    }
+    visible
+}
+
+pub fn cull_coloured(objects: &[ColouredObject], meshes: &MeshRegistry, view_proj: Mat4,
+    stats: &mut CullStats) -> Vec<ColouredObject> {
+    let frustum = Frustum::from_matrix(view_proj);
+    let visible: Vec<ColouredObject> = objects.iter()
+        .filter(|cobj| frustum.contains(meshes, &cobj.object))
+        .copied()
+        .collect();
+    stats.visible += visible.len();
+    stats.culled += objects.len() - visible.len();
+    visible
+}
@@ -0,0 +1,247 @@
+use glam::{Vec3, Vec4, Quat, Mat4};
+use crate::objects::{self, Object, ColouredObject};
+
+// a small entity/component store for things that move on their own. every
+// entity has a mesh and a transform, the other components are optional and
+// stored per entity index. step() runs each system once per simulation step
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.position)
+    }
+
+    fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+// units per second
+#[derive(Clone, Copy, Debug)]
+pub struct Velocity(pub Vec3);
+
+// radians per second about the axis it points along
+#[derive(Clone, Copy, Debug)]
+pub struct AngularVelocity(pub Vec3);
+
+// rests height above the ground, rising and falling by amplitude rate
+// times a second
+#[derive(Clone, Copy, Debug)]
+pub struct Hover {
+    pub height: f32,
+    pub amplitude: f32,
+    pub rate: f32,
+    pub phase: f32,
+}
+
+// brightened and dimmed by up to pulse, rate times a second
+#[derive(Clone, Copy, Debug)]
+pub struct ColourAnimation {
+    pub colour: Vec4,
+    pub pulse: f32,
+    pub rate: f32,
+    pub phase: f32,
+}
+
+impl ColourAnimation {
+    fn at(&self, time: f32) -> Vec4 {
+        let wave = (std::f32::consts::TAU * (self.rate * time + self.phase)).sin();
+        let c = self.colour.truncate() * (1.0 + self.pulse * wave);
+        c.clamp(Vec3::ZERO, Vec3::ONE).extend(self.colour.w)
+    }
+}
+
+// kept within spread of the centre across x and z, wrapping to the far
+// side when it drifts out
+#[derive(Clone, Copy, Debug)]
+pub struct Wrap {
+    pub spread: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entity(usize);
+
+#[derive(Default)]
+pub struct Entities {
+    // simulation time in seconds
+    time: f32,
+    meshes: Vec<Object>,
+    transforms: Vec<Transform>,
+    // transforms before the latest step, for drawing between the two
+    previous: Vec<Transform>,
+    velocities: Vec<Option<Velocity>>,
+    angular_velocities: Vec<Option<AngularVelocity>>,
+    hovers: Vec<Option<Hover>>,
+    colours: Vec<Option<ColourAnimation>>,
+    wraps: Vec<Option<Wrap>>,
+}
+
+impl Entities {
+    pub fn new() -> Entities {
+        Entities::default()
+    }
+
+    // an entity drawing mesh with its model replaced by the transform
+    pub fn spawn(&mut self, mesh: Object, transform: Transform) -> Entity {
+        self.meshes.push(mesh);
+        self.transforms.push(transform);
+        self.previous.push(transform);
+        self.velocities.push(None);
+        self.angular_velocities.push(None);
+        self.hovers.push(None);
+        self.colours.push(None);
+        self.wraps.push(None);
+        Entity(self.meshes.len() - 1)
+    }
+
+    pub fn set_velocity(&mut self, e: Entity, v: Velocity) {
+        self.velocities[e.0] = Some(v);
+    }
+
+    pub fn set_angular_velocity(&mut self, e: Entity, w: AngularVelocity) {
+        self.angular_velocities[e.0] = Some(w);
+    }
+
+    pub fn set_hover(&mut self, e: Entity, hover: Hover) {
+        self.hovers[e.0] = Some(hover);
+    }
+
+    pub fn set_colour(&mut self, e: Entity, colour: ColourAnimation) {
+        self.colours[e.0] = Some(colour);
+    }
+
+    pub fn set_wrap(&mut self, e: Entity, wrap: Wrap) {
+        self.wraps[e.0] = Some(wrap);
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    // advance every entity by dt seconds. centre is what Wrap keeps them
+    // around and ground gives the height of the ground at x, z
+    pub fn step(&mut self, dt: f32, centre: Vec3, ground: impl Fn(f32, f32) -> f32) {
+        self.time += dt;
+        self.previous.clone_from(&self.transforms);
+        for i in 0..self.transforms.len() {
+            let t = &mut self.transforms[i];
+            if let Some(v) = self.velocities[i] {
+                t.position += v.0 * dt;
+            }
+            if let Some(w) = self.angular_velocities[i] {
+                t.rotation = (Quat::from_scaled_axis(w.0 * dt) * t.rotation).normalize();
+            }
+            if let Some(wrap) = self.wraps[i] {
+                let spread = Vec3::splat(wrap.spread);
+                let mut p = objects::wrap(t.position, centre - spread, centre + spread);
+                p.y = t.position.y;
+                // move the previous one the same so it isn't drawn sweeping
+                // across the whole area
+                self.previous[i].position += p - t.position;
+                t.position = p;
+            }
+            if let Some(hover) = self.hovers[i] {
+                let wave = (std::f32::consts::TAU * (hover.rate * self.time + hover.phase)).sin();
+                t.position.y = ground(t.position.x, t.position.z) + hover.height
+                    + hover.amplitude * wave;
+            }
+        }
+    }
+
+    // everything to draw, alpha of the way from the previous step to the
+    // latest. entities with a colour come out coloured
+    pub fn objects(&self, alpha: f32) -> (Vec<Object>, Vec<ColouredObject>) {
+        let mut objects = Vec::<Object>::new();
+        let mut coloured_objects = Vec::<ColouredObject>::new();
+        for i in 0..self.meshes.len() {
+            let t = self.previous[i].lerp(&self.transforms[i], alpha);
+            let object = Object {
+                model: t.matrix(),
+                ..self.meshes[i]
+            };
+            match self.colours[i] {
+                Some(colour) => coloured_objects.push(ColouredObject {
+                    object,
+                    colour: colour.at(self.time),
+                }),
+                None => objects.push(object),
+            }
+        }
+        (objects, coloured_objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{vec3, vec4};
+    use crate::meshes;
+
+    fn spawn_at(entities: &mut Entities, position: Vec3) -> Entity {
+        let mesh = Object {
+            model: Mat4::IDENTITY,
+            mesh: meshes::builtin("cube").unwrap(),
+        };
+        entities.spawn(mesh, Transform {
+            position,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        })
+    }
+
+    #[test]
+    fn wrapped_entities_stay_within_spread() {
+        let mut entities = Entities::new();
+        let centre = vec3(10.0, 0.0, -3.0);
+        let spread = 4.0;
+        // fast enough to cross the whole area several times a step
+        let e = spawn_at(&mut entities, centre);
+        entities.set_velocity(e, Velocity(vec3(-370.0, 0.0, 530.0)));
+        entities.set_wrap(e, Wrap { spread });
+        for _ in 0..1000 {
+            entities.step(0.1, centre, |_, _| 0.0);
+            let p = entities.transforms[e.0].position;
+            assert!((p.x - centre.x).abs() <= spread, "x {} out of range", p.x);
+            assert!((p.z - centre.z).abs() <= spread, "z {} out of range", p.z);
+        }
+    }
+
+    #[test]
+    fn hover_follows_the_ground_and_colour_pulses() {
+        let mut entities = Entities::new();
+        let e = spawn_at(&mut entities, vec3(2.0, 0.0, 0.0));
+        entities.set_hover(e, Hover { height: 1.0, amplitude: 0.25, rate: 1.0, phase: 0.0 });
+        entities.set_colour(e, ColourAnimation {
+            colour: vec4(0.5, 0.5, 0.5, 0.8),
+            pulse: 0.5,
+            rate: 1.0,
+            phase: 0.0,
+        });
+        let ground = |x: f32, _: f32| x * 0.5;
+
+        // a quarter of the way through the wave, at its top
+        entities.step(0.25, Vec3::ZERO, ground);
+        let (objects, coloured) = entities.objects(1.0);
+        assert!(objects.is_empty());
+        let y = coloured[0].object.model.w_axis.y;
+        assert!((y - 2.25).abs() < 1e-5, "y {}", y);
+        assert!(coloured[0].colour.abs_diff_eq(vec4(0.75, 0.75, 0.75, 0.8), 1e-5));
+
+        // three quarters, at the bottom
+        entities.step(0.5, Vec3::ZERO, ground);
+        let (_, coloured) = entities.objects(1.0);
+        let y = coloured[0].object.model.w_axis.y;
+        assert!((y - 1.75).abs() < 1e-5, "y {}", y);
+        assert!(coloured[0].colour.abs_diff_eq(vec4(0.25, 0.25, 0.25, 0.8), 1e-5));
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+use glam::{Vec3, Mat4};
+use mq_test::mesh::{Mesh, Vertex};
+use crate::meshes::MeshRegistry;
+use crate::objects::{Object, ColouredObject};
+use crate::main_pipe::normal_matrix;
+
+// writes objects and terrain patches to a Wavefront OBJ, with the
+// materials in an MTL beside it, for checking the world in other tools.
+// positions are as drawn, with scene_model applied. coloured objects get a
+// material each that emits their colour
+pub fn write_obj(path: &Path, meshes: &MeshRegistry, objects: &[Object],
+    coloured_objects: &[ColouredObject], terrain: &[&Mesh],
+    scene_model: Mat4) -> Result<(), String> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path.file_name()
+        .ok_or_else(|| format!("{}: not a file name", path.display()))?
+        .to_string_lossy();
+
+    let mut obj = format!("mtllib {}\n", mtl_name);
+    let mut mtl = String::from("newmtl plain\nKd 1 1 1\n");
+    // obj indices count from one across the whole file
+    let mut next = 1;
+    for (i, patch) in terrain.iter().enumerate() {
+        write_object(&mut obj, &format!("terrain_{}", i), "plain",
+            scene_model, &patch.vertices, &patch.indices, &mut next);
+    }
+    for (i, o) in objects.iter().enumerate() {
+        write_mesh_object(&mut obj, &format!("object_{}", i), "plain",
+            scene_model * o.model, meshes, o, &mut next);
+    }
+    for (i, cobj) in coloured_objects.iter().enumerate() {
+        let material = format!("colour_{}", i);
+        let c = cobj.colour;
+        mtl.push_str(&format!("\nnewmtl {}\nKd {} {} {}\nKe {} {} {}\nd {}\n",
+            material, c.x, c.y, c.z, c.x, c.y, c.z, c.w));
+        write_mesh_object(&mut obj, &format!("coloured_{}", i), &material,
+            scene_model * cobj.object.model, meshes, &cobj.object, &mut next);
+    }
+
+    std::fs::write(path, obj).map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::fs::write(&mtl_path, mtl).map_err(|e| format!("{}: {}", mtl_path.display(), e))
+}
+
+// write_object for an object's mesh in the registry
+fn write_mesh_object(out: &mut String, name: &str, material: &str, model: Mat4,
+    meshes: &MeshRegistry, obj: &Object, next: &mut usize) {
+    let page = &meshes.pages()[meshes.get(obj.mesh).page];
+    write_object(out, name, material, model, &page.vertices, meshes.indices(obj.mesh), next);
+}
+
+// the faces in range, and the vertices they use transformed by model
+fn write_object(out: &mut String, name: &str, material: &str, model: Mat4,
+    vertices: &[Vertex], range: &[u16], next: &mut usize) {
+    let first = *range.iter().min().unwrap_or(&0) as usize;
+    let last = *range.iter().max().unwrap_or(&0) as usize;
+    let vertices = &vertices[first..=last];
+    let normals = normal_matrix(model);
+    out.push_str(&format!("o {}\nusemtl {}\n", name, material));
+    for v in vertices.iter() {
+        let p = model.transform_point3(v.position());
+        let n = normals.transform_vector3(Vec3::from(v.normal)).normalize();
+        out.push_str(&format!("v {} {} {}\nvn {} {} {}\nvt {} {}\n",
+            p.x, p.y, p.z, n.x, n.y, n.z, v.uv[0], v.uv[1]));
+    }
+    for tri in range.chunks(3) {
+        out.push('f');
+        for i in tri.iter() {
+            let i = *i as usize - first + *next;
+            out.push_str(&format!(" {}/{}/{}", i, i, i));
+        }
+        out.push('\n');
+    }
+    *next += vertices.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{vec3, vec4};
+
+    // one triangle facing +z
+    fn triangle() -> Mesh {
+        Mesh::from_slices(&[
+            0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0,
+            0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0,
+        ], &[0, 1, 2])
+    }
+
+    #[test]
+    fn objects_and_terrain_are_written_with_their_materials() {
+        let mut meshes = MeshRegistry::new();
+        let mesh = meshes.add(&triangle()).unwrap();
+        let objects = [Object {
+            model: Mat4::from_translation(vec3(2.0, 0.0, 0.0)),
+            mesh,
+        }];
+        let coloured_objects = [ColouredObject {
+            object: Object { model: Mat4::IDENTITY, mesh },
+            colour: vec4(1.0, 0.5, 0.0, 1.0),
+        }];
+        let terrain = triangle();
+
+        let path = std::env::temp_dir()
+            .join(format!("export_{}.obj", std::process::id()));
+        let written = write_obj(&path, &meshes, &objects, &coloured_objects,
+            &[&terrain], Mat4::from_translation(vec3(0.0, 0.0, 1.0)));
+        let obj = std::fs::read_to_string(&path);
+        let mtl = std::fs::read_to_string(path.with_extension("mtl"));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("mtl")).unwrap();
+        written.unwrap();
+
+        let obj = obj.unwrap();
+        assert_eq!(obj.lines().next(),
+            Some(format!("mtllib export_{}.mtl", std::process::id()).as_str()));
+        let lines: Vec<&str> = obj.lines()
+            .filter(|l| ["o ", "usemtl ", "v ", "f "].iter().any(|p| l.starts_with(p)))
+            .collect();
+        assert_eq!(lines, vec![
+            "o terrain_0", "usemtl plain",
+            "v 0 0 1", "v 1 0 1", "v 0 1 1",
+            "f 1/1/1 2/2/2 3/3/3",
+            "o object_0", "usemtl plain",
+            "v 2 0 1", "v 3 0 1", "v 2 1 1",
+            "f 4/4/4 5/5/5 6/6/6",
+            "o coloured_0", "usemtl colour_0",
+            "v 0 0 1", "v 1 0 1", "v 0 1 1",
+            "f 7/7/7 8/8/8 9/9/9",
+        ]);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("vn 0 0 1")).count(), 9);
+        assert_eq!(mtl.unwrap(),
+            "newmtl plain\nKd 1 1 1\n\nnewmtl colour_0\nKd 1 0.5 0\nKe 1 0.5 0\nd 1\n");
+    }
+}
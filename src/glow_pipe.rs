@@ -1,37 +1,30 @@
 use miniquad::*;
-use glam::Mat4;
-use crate::objects::{Object, ColouredObject};
-use crate::blur_pipe::BlurPipe;
+use mq_test::backend::{Backend, Pod};
+use mq_test::gpu::{self, OwnedPipeline};
+use mq_test::mesh::Vertex;
+use mq_test::render_queue::{DrawItem, RenderQueue, State};
+use crate::blur::Blur;
+use crate::blur_pipe;
+use crate::instancing::{self, Batches, InstanceBuffer};
+use crate::terrain;
+use crate::render_graph::{Frame, RenderNode};
+use crate::target::{Size, Target};
 
 pub struct GlowPipe {
-    pass:RenderPass,
-    pipe:Pipeline,
-    blur_pipe:BlurPipe,
+    target:Target,
+    pipe:OwnedPipeline,
+    instanced_pipe:OwnedPipeline,
+    binds:Vec<Bindings>,
+    instanced_binds:Vec<Bindings>,
+    instances:InstanceBuffer,
+    blur_pipe:Blur,
     output:Texture
 }
 
 impl GlowPipe {
-    pub fn new(ctx: &mut Context) -> GlowPipe {
-        let color_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: 256,
-                height: 256,
-                format: TextureFormat::RGBA8,
-                ..Default::default()
-            },
-        );
-        let depth_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: 256,
-                height: 256,
-                format: TextureFormat::Depth,
-                ..Default::default()
-            },
-        );
-
-        let pass = RenderPass::new(ctx, color_img, depth_img);
+    pub fn new(ctx: &mut Context, binds: Vec<Bindings>, quad: &Bindings,
+        size: Size) -> GlowPipe {
+        let target = Target::new(ctx, "glow", size, TextureFormat::RGBA8, true);
 
         let shader = Shader::new(
             ctx,
@@ -41,68 +34,128 @@ impl GlowPipe {
         )
         .unwrap();
 
-        let pipe = Pipeline::with_params(
+        let pipe = gpu::checked_pipeline(
             ctx,
-            &[BufferLayout {
-                stride: 36,
-                ..Default::default()
-            }],
-            &[
-                VertexAttribute::new("pos", VertexFormat::Float3),
-            ],
+            VERTEX,
+            &[Vertex::layout()],
+            &Vertex::attributes(),
             shader,
             PipelineParams {
                 depth_test: Comparison::LessOrEqual,
                 depth_write: true,
                 ..Default::default()
             },
-        );
+        )
+        .unwrap();
+
+        let shader = Shader::new(
+            ctx,
+            instancing::COLOURED_VERTEX,
+            instancing::COLOURED_FRAGMENT,
+            instancing::coloured_meta(),
+        )
+        .unwrap();
 
-        let blur_pipe = BlurPipe::new(ctx, 3.0, color_img);
+        let instanced_pipe = instancing::pipeline(ctx,
+            instancing::COLOURED_VERTEX, shader).unwrap();
+        let instances = InstanceBuffer::new(ctx);
+        let instanced_binds = binds.iter().map(|b| instances.bind(b)).collect();
+
+        let blur_pipe = Blur::new(ctx, quad, size, 3.0, target.output(),
+            blur_pipe::shaders());
         let output = blur_pipe.get_output();
 
         GlowPipe {
-            pass,
-            pipe,
+            target,
+            pipe: OwnedPipeline::new(pipe, "glow"),
+            instanced_pipe: OwnedPipeline::new(instanced_pipe, "glow instanced"),
+            binds,
+            instanced_binds,
+            instances,
             blur_pipe,
             output
         }
     }
 
-    pub fn draw(&self, ctx: &mut Context,
-        bind: &Bindings,
-        objects: &Vec<Object>, 
-        coloured_objects: &Vec<ColouredObject>,
-        model: &Mat4, view_proj: &Mat4) {
-        ctx.begin_pass(
-            self.pass,
+}
+
+impl RenderNode for GlowPipe {
+    fn outputs(&self) -> &[&'static str] {
+        &["glow"]
+    }
+
+    fn get_output(&self, _slot: usize) -> Option<Texture> {
+        Some(self.output)
+    }
+
+    fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if self.target.resize(ctx, width, height) {
+            self.blur_pipe.set_input(self.target.output());
+        }
+        self.blur_pipe.resize(ctx, width, height);
+        self.output = self.blur_pipe.get_output();
+    }
+
+    fn draw(&self, gfx: &mut dyn Backend, frame: &Frame) {
+        gfx.begin_pass(
+            Some(self.target.pass()),
             PassAction::clear_color(0.0, 0.0, 0.0, 0.0),
         );
-        ctx.apply_pipeline(&self.pipe);
-        ctx.apply_bindings(bind);
-        for obj in objects.iter() {
-            ctx.apply_uniforms(&Uniforms {
-                mvp: *view_proj * *model * obj.model,
-                colour: glam::vec4(0., 0., 0., 0.)
-            });
-            ctx.draw(obj.start, obj.end, 1);
+        let mut queue: RenderQueue = RenderQueue::new();
+        // shared by the instanced objects and the terrain
+        let instanced = queue.pipeline(&self.instanced_pipe);
+        if frame.instanced {
+            let states = queue.states(instanced, &self.instanced_binds);
+            queue_instanced(&mut queue, &states, &self.instances, frame);
+        } else {
+            let pipeline = queue.pipeline(&self.pipe);
+            let states = queue.states(pipeline, &self.binds);
+            queue_objects(&mut queue, &states, frame);
         }
-        for obj in coloured_objects.iter() {
-            ctx.apply_uniforms(&Uniforms {
-                mvp: *view_proj * *model * obj.object.model,
-                colour: obj.colour
+        // the terrain in black so it hides glow behind hills
+        terrain::queue_patches(&mut queue, instanced, &self.instanced_binds[0],
+            frame.terrain, glam::vec4(0., 0., 0., 0.), &instancing::ColouredUniforms {
+                mvp: frame.view_proj * frame.scene_model
             });
-            ctx.draw(obj.object.start, obj.object.end, 1);
-        }
-        ctx.end_render_pass();
-        self.blur_pipe.draw(ctx);
+        frame.stats.borrow_mut().add(queue.flush(gfx));
+        gfx.end_render_pass();
+        self.blur_pipe.draw(gfx, frame);
     }
+}
 
-    pub fn get_output(&self) -> Texture {
-        self.output
+// plain objects in black to occlude the glow, then the coloured ones
+pub fn queue_objects(queue: &mut RenderQueue, states: &[State], frame: &Frame) {
+    let view_proj = frame.view_proj * frame.scene_model;
+    for obj in frame.objects.iter() {
+        let mvp = view_proj * obj.model;
+        let range = frame.meshes.get(obj.mesh);
+        queue.submit(DrawItem::new(states[range.page], range.start, range.count, &Uniforms {
+            mvp,
+            colour: glam::vec4(0., 0., 0., 0.)
+        }, mvp.w_axis.w));
+    }
+    for obj in frame.coloured_objects.iter() {
+        let mvp = view_proj * obj.object.model;
+        let range = frame.meshes.get(obj.object.mesh);
+        queue.submit(DrawItem::new(states[range.page], range.start, range.count, &Uniforms {
+            mvp,
+            colour: obj.colour
+        }, mvp.w_axis.w));
     }
 }
 
+// the same with one draw per mesh range
+pub fn queue_instanced(queue: &mut RenderQueue, states: &[State],
+    instances: &InstanceBuffer, frame: &Frame) {
+    let mut batches = Batches::objects(frame.objects, glam::vec4(0., 0., 0., 0.));
+    for cobj in frame.coloured_objects.iter() {
+        batches.push(&cobj.object, cobj.colour);
+    }
+    let view_proj = frame.view_proj * frame.scene_model;
+    instances.submit(queue, states, frame.meshes, batches, view_proj,
+        &instancing::ColouredUniforms { mvp: view_proj });
+}
+
 pub const VERTEX: &str = r#"#version 100
 attribute vec4 pos;
 
@@ -135,7 +188,10 @@ pub fn meta() -> ShaderMeta {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Uniforms {
     pub mvp: glam::Mat4,
     pub colour: glam::Vec4,
-}
\ No newline at end of file
+}
+
+unsafe impl Pod for Uniforms {}
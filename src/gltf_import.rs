@@ -0,0 +1,244 @@
+use std::path::Path;
+use glam::{vec4, Vec2, Vec3, Vec4, Mat4};
+use gltf::mesh::Mode;
+use mq_test::mesh::{Mesh, Vertex};
+use crate::meshes::{MeshId, MeshRegistry};
+use crate::objects::{Object, ColouredObject};
+
+// meshes from gltf and glb files, placed by their node hierarchy. base
+// colours are baked into the vertex colours, and emissive materials glow.
+// textures, skins, morph targets, animations and anything else unsupported
+// are skipped with a warning
+
+pub struct Import {
+    pub objects: Vec<Object>,
+    pub coloured_objects: Vec<ColouredObject>,
+    // what was skipped
+    pub warnings: Vec<String>,
+}
+
+// a primitive, added to the registry
+#[derive(Clone, Copy)]
+struct Part {
+    mesh: MeshId,
+    // the emissive factor, if there is one
+    glow: Option<Vec4>,
+}
+
+impl Import {
+    fn warn(&mut self, warning: String) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+}
+
+// the file's objects, with its primitives added to meshes
+pub fn load(path: &Path, meshes: &mut MeshRegistry) -> Result<Import, String> {
+    let at = |e: gltf::Error| format!("{}: {}", path.display(), e);
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(at)?;
+    let buffers = gltf::import_buffers(&document, path.parent(), blob).map_err(at)?;
+
+    let mut import = Import {
+        objects: vec![],
+        coloured_objects: vec![],
+        warnings: vec![],
+    };
+    for ext in document.extensions_required() {
+        import.warn(format!("required extension {} is not supported", ext));
+    }
+    if document.animations().next().is_some() {
+        import.warn("animations are not supported".to_string());
+    }
+
+    let mut parts = Vec::<Vec<Part>>::new();
+    for mesh in document.meshes() {
+        let mut mesh_parts = vec![];
+        for (i, prim) in mesh.primitives().enumerate() {
+            let name = format!("mesh {} primitive {}", mesh.index(), i);
+            match read_primitive(&prim, &buffers, meshes, &mut import) {
+                Ok(part) => mesh_parts.push(part),
+                Err(e) => import.warn(format!("{} skipped: {}", name, e)),
+            }
+        }
+        parts.push(mesh_parts);
+    }
+
+    let scene = document.default_scene().or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{}: no scenes", path.display()))?;
+    for node in scene.nodes() {
+        place(&node, Mat4::IDENTITY, &parts, &mut import);
+    }
+    Ok(import)
+}
+
+fn read_primitive(prim: &gltf::Primitive, buffers: &[gltf::buffer::Data],
+    meshes: &mut MeshRegistry, import: &mut Import) -> Result<Part, String> {
+    if prim.mode() != Mode::Triangles {
+        return Err(format!("{:?} is not supported, only triangles", prim.mode()));
+    }
+    let reader = prim.reader(|b| buffers.get(b.index()).map(|d| &d.0[..]));
+    let positions: Vec<Vec3> = reader.read_positions()
+        .ok_or("no positions")?
+        .map(Vec3::from)
+        .collect();
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(i) = indices.iter().find(|i| **i as usize >= positions.len()) {
+        return Err(format!("index {} out of range, there are {} vertices", i, positions.len()));
+    }
+    let normals: Vec<Vec3> = match reader.read_normals() {
+        Some(normals) => normals.map(Vec3::from).collect(),
+        None => smooth_normals(&positions, &indices),
+    };
+    let uvs: Vec<Vec2> = match reader.read_tex_coords(0) {
+        Some(uvs) => uvs.into_f32().map(Vec2::from).collect(),
+        None => vec![Vec2::ZERO; positions.len()],
+    };
+    let colours: Vec<Vec4> = match reader.read_colors(0) {
+        Some(colours) => colours.into_rgba_f32().map(Vec4::from).collect(),
+        None => vec![vec4(1.0, 1.0, 1.0, 1.0); positions.len()],
+    };
+    // every attribute is indexed by vertex, so a short one would be read
+    // past its end
+    for (what, len) in [("normals", normals.len()), ("uvs", uvs.len()),
+        ("colours", colours.len())].iter() {
+        if *len != positions.len() {
+            return Err(format!("{} {} for {} positions", len, what, positions.len()));
+        }
+    }
+    if prim.morph_targets().next().is_some() {
+        import.warn("morph targets are not supported".to_string());
+    }
+
+    let material = prim.material();
+    let pbr = material.pbr_metallic_roughness();
+    if pbr.base_color_texture().is_some() || material.emissive_texture().is_some()
+        || material.normal_texture().is_some() {
+        import.warn("textures are not supported, only colour factors".to_string());
+    }
+    let base = Vec4::from(pbr.base_color_factor());
+    let emissive = Vec3::from(material.emissive_factor());
+
+    let mut mesh = Mesh::new();
+    for (i, p) in positions.iter().enumerate() {
+        mesh.push(Vertex::new(*p, colours[i] * base, normals[i], uvs[i]))?;
+    }
+    mesh.indices = indices.iter().map(|i| *i as u16).collect();
+
+    Ok(Part {
+        mesh: meshes.add(&mesh)?,
+        glow: if emissive.max_element() > 0.0 { Some(emissive.extend(1.0)) } else { None },
+    })
+}
+
+// averaged from the triangles around each vertex, for primitives that
+// come without normals
+fn smooth_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for t in indices.chunks_exact(3) {
+        let (a, b, c) = (positions[t[0] as usize], positions[t[1] as usize],
+            positions[t[2] as usize]);
+        let n = (b - a).cross(c - a);
+        for i in t.iter() {
+            normals[*i as usize] += n;
+        }
+    }
+    normals.iter().map(|n| n.normalize_or_zero()).collect()
+}
+
+fn place(node: &gltf::Node, parent: Mat4, parts: &[Vec<Part>], import: &mut Import) {
+    let model = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if node.skin().is_some() {
+        import.warn("skins are not supported, skinned meshes are drawn unposed".to_string());
+    }
+    if let Some(mesh) = node.mesh() {
+        for part in parts[mesh.index()].iter() {
+            let object = Object { model, mesh: part.mesh };
+            match part.glow {
+                Some(colour) => import.coloured_objects.push(ColouredObject { object, colour }),
+                None => import.objects.push(object),
+            }
+        }
+    }
+    for child in node.children() {
+        place(&child, model, parts, import);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one triangle drawn twice, the second time with only two normals
+    const SHORT_NORMALS: &str = r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 60, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/"}],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 24}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]},
+            {"bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC3"}
+        ],
+        "meshes": [
+            {"primitives": [{"attributes": {"POSITION": 0}}]},
+            {"primitives": [{"attributes": {"POSITION": 0, "NORMAL": 1}}]}
+        ],
+        "nodes": [{"mesh": 0}, {"mesh": 1}],
+        "scenes": [{"nodes": [0, 1]}]
+    }"#;
+
+    // the same triangle in an orange material
+    const ORANGE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 60, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/"}],
+        "bufferViews": [{"buffer": 0, "byteOffset": 0, "byteLength": 36}],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]}
+        ],
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1.0, 0.5, 0.25, 1.0]}}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+        "nodes": [{"mesh": 0}],
+        "scenes": [{"nodes": [0]}]
+    }"#;
+
+    fn load_text(name: &str, text: &str, meshes: &mut MeshRegistry) -> Import {
+        let path = std::env::temp_dir()
+            .join(format!("{}_{}.gltf", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let import = load(&path, meshes);
+        std::fs::remove_file(&path).unwrap();
+        import.unwrap()
+    }
+
+    #[test]
+    fn base_colours_become_vertex_colours() {
+        let mut meshes = MeshRegistry::new();
+        let import = load_text("orange", ORANGE, &mut meshes);
+        assert!(import.warnings.is_empty());
+        assert_eq!(import.objects.len(), 1);
+        // the lit pipes multiply by the vertex colour
+        let mesh = import.objects[0].mesh;
+        let geometry = &meshes.pages()[meshes.get(mesh).page];
+        let corners = meshes.indices(mesh);
+        assert_eq!(corners.len(), 3);
+        for i in corners.iter() {
+            assert_eq!(geometry.vertices[*i as usize].colour, [1.0, 0.5, 0.25, 1.0]);
+        }
+    }
+
+    #[test]
+    fn primitives_with_short_attributes_are_skipped() {
+        let mut meshes = MeshRegistry::new();
+        let import = load_text("short_normals", SHORT_NORMALS, &mut meshes);
+        assert_eq!(import.objects.len(), 1);
+        assert_eq!(import.warnings,
+            vec!["mesh 1 primitive 0 skipped: 2 normals for 3 positions".to_string()]);
+    }
+}
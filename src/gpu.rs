@@ -0,0 +1,336 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use miniquad::*;
+
+// owning wrappers for miniquad handles. textures and buffers are freed as
+// soon as their owner is dropped; render passes can only be deleted with
+// the Context so they wait for the next call to collect(). miniquad has
+// no way to delete a pipeline, so those are only tracked
+
+struct Live {
+    kind: &'static str,
+    label: &'static str,
+    detail: String,
+    bytes: usize,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: usize,
+    live: BTreeMap<usize, Live>,
+    dead_passes: Vec<RenderPass>,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+}
+
+fn register(kind: &'static str, label: &'static str, detail: String, bytes: usize) -> usize {
+    REGISTRY.with(|r| {
+        let mut r = r.borrow_mut();
+        let id = r.next_id;
+        r.next_id += 1;
+        r.live.insert(id, Live { kind, label, detail, bytes });
+        id
+    })
+}
+
+fn unregister(id: usize) {
+    REGISTRY.with(|r| {
+        r.borrow_mut().live.remove(&id);
+    });
+}
+
+fn bytes_per_pixel(format: TextureFormat) -> usize {
+    match format {
+        TextureFormat::RGB8 => 3,
+        TextureFormat::Alpha => 1,
+        _ => 4,
+    }
+}
+
+pub struct OwnedTexture {
+    tex: Texture,
+    id: usize,
+}
+
+impl OwnedTexture {
+    pub fn new(tex: Texture, label: &'static str) -> OwnedTexture {
+        let detail = format!("{}x{} {:?}", tex.width, tex.height, tex.format);
+        let bytes = tex.width as usize * tex.height as usize
+            * bytes_per_pixel(tex.format);
+        let id = register("texture", label, detail, bytes);
+        OwnedTexture { tex, id }
+    }
+}
+
+impl Deref for OwnedTexture {
+    type Target = Texture;
+
+    fn deref(&self) -> &Texture {
+        &self.tex
+    }
+}
+
+impl Drop for OwnedTexture {
+    fn drop(&mut self) {
+        self.tex.delete();
+        unregister(self.id);
+    }
+}
+
+pub struct OwnedBuffer {
+    buf: Buffer,
+    id: usize,
+}
+
+impl OwnedBuffer {
+    pub fn new(buf: Buffer, label: &'static str) -> OwnedBuffer {
+        let id = register("buffer", label, String::new(), buf.size());
+        OwnedBuffer { buf, id }
+    }
+}
+
+impl Deref for OwnedBuffer {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl Drop for OwnedBuffer {
+    fn drop(&mut self) {
+        self.buf.delete();
+        unregister(self.id);
+    }
+}
+
+pub struct OwnedPass {
+    pass: RenderPass,
+    id: usize,
+}
+
+impl OwnedPass {
+    pub fn new(pass: RenderPass, label: &'static str) -> OwnedPass {
+        let id = register("pass", label, String::new(), 0);
+        OwnedPass { pass, id }
+    }
+}
+
+impl Deref for OwnedPass {
+    type Target = RenderPass;
+
+    fn deref(&self) -> &RenderPass {
+        &self.pass
+    }
+}
+
+impl Drop for OwnedPass {
+    fn drop(&mut self) {
+        let pass = self.pass;
+        REGISTRY.with(|r| r.borrow_mut().dead_passes.push(pass));
+        unregister(self.id);
+    }
+}
+
+pub struct OwnedPipeline {
+    pipe: Pipeline,
+    id: usize,
+}
+
+impl OwnedPipeline {
+    pub fn new(pipe: Pipeline, label: &'static str) -> OwnedPipeline {
+        let id = register("pipeline", label, String::new(), 0);
+        OwnedPipeline { pipe, id }
+    }
+}
+
+impl Deref for OwnedPipeline {
+    type Target = Pipeline;
+
+    fn deref(&self) -> &Pipeline {
+        &self.pipe
+    }
+}
+
+impl Drop for OwnedPipeline {
+    fn drop(&mut self) {
+        unregister(self.id);
+    }
+}
+
+// take ownership of the buffers in a set of bindings
+pub fn own_buffers(bind: &Bindings, label: &'static str) -> Vec<OwnedBuffer> {
+    let mut buffers: Vec<OwnedBuffer> = bind.vertex_buffers.iter()
+        .map(|b| OwnedBuffer::new(*b, label))
+        .collect();
+    buffers.push(OwnedBuffer::new(bind.index_buffer, label));
+    buffers
+}
+
+// delete the render passes dropped since the last call
+pub fn collect(ctx: &mut Context) {
+    let dead = REGISTRY.with(|r| std::mem::take(&mut r.borrow_mut().dead_passes));
+    for pass in dead {
+        pass.delete(ctx);
+    }
+}
+
+// one line per live resource and a total of the memory they hold
+pub fn report() -> String {
+    REGISTRY.with(|r| {
+        let r = r.borrow();
+        let mut out = String::new();
+        let mut total = 0;
+        for (id, live) in r.live.iter() {
+            out += &format!("{:4} {:8} {:16} {:24} {:>10} bytes\n",
+                id, live.kind, live.label, live.detail, live.bytes);
+            total += live.bytes;
+        }
+        out += &format!("{} live resources, {} bytes\n", r.live.len(), total);
+        out
+    })
+}
+
+// the name and type of each attribute a glsl vertex shader declares
+fn declared_attributes(source: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut declared = vec![];
+    for line in source.lines() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        let words: Vec<&str> = line.trim_end_matches(';').split_whitespace().collect();
+        if words.first() != Some(&"attribute") {
+            continue;
+        }
+        // skip a precision qualifier
+        match words[1..] {
+            [ty, name] | [_, ty, name] => declared.push((name, ty)),
+            _ => return Err(format!("can't read attribute \"{}\"", line)),
+        }
+    }
+    Ok(declared)
+}
+
+// whether an attribute of glsl type ty can read format. vectors may be
+// wider than what they're given, as pos reads a Float3 into a vec4
+fn readable(ty: &str, format: VertexFormat) -> bool {
+    let components = match ty {
+        "float" => 1,
+        "vec2" => 2,
+        "vec3" => 3,
+        "vec4" => 4,
+        "mat4" => return format == VertexFormat::Mat4,
+        _ => return false,
+    };
+    format != VertexFormat::Mat4 && format.size() <= components
+}
+
+// that the attributes fill their buffer strides exactly and give every
+// attribute vertex_source declares something it can read. shaders may
+// leave attributes out; they're still stepped over
+pub fn check_layout(vertex_source: &str, buffer_layouts: &[BufferLayout],
+    attributes: &[VertexAttribute]) -> Result<(), String> {
+    for (i, layout) in buffer_layouts.iter().enumerate() {
+        let bytes: i32 = attributes.iter()
+            .filter(|a| a.buffer_index == i)
+            .map(|a| a.format.byte_len())
+            .sum();
+        // a stride of 0 is worked out from the attributes, so always fits
+        if layout.stride != 0 && layout.stride != bytes {
+            return Err(format!("buffer {} has stride {} but its attributes take {} bytes",
+                i, layout.stride, bytes));
+        }
+    }
+    for (name, ty) in declared_attributes(vertex_source)? {
+        let attribute = attributes.iter().find(|a| a.name == name)
+            .ok_or_else(|| format!("shader attribute {} is not in the layout", name))?;
+        if !readable(ty, attribute.format) {
+            return Err(format!("shader attribute {} is a {} but the layout gives {:?}",
+                name, ty, attribute.format));
+        }
+    }
+    Ok(())
+}
+
+// a pipeline, once check_layout passes
+pub fn checked_pipeline(ctx: &mut Context, vertex_source: &str,
+    buffer_layouts: &[BufferLayout], attributes: &[VertexAttribute],
+    shader: Shader, params: PipelineParams) -> Result<Pipeline, String> {
+    check_layout(vertex_source, buffer_layouts, attributes)?;
+    Ok(Pipeline::with_params(ctx, buffer_layouts, attributes, shader, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERTEX: &str = "#version 100
+attribute vec4 pos; // read from a Float3
+attribute lowp vec4 colour;
+attribute mediump vec2 uv;
+uniform mat4 mvp;
+void main() {
+    gl_Position = mvp * pos;
+}
+";
+
+    fn attributes() -> Vec<VertexAttribute> {
+        vec![
+            VertexAttribute::new("pos", VertexFormat::Float3),
+            VertexAttribute::new("colour", VertexFormat::Float4),
+            VertexAttribute::new("uv", VertexFormat::Float2),
+        ]
+    }
+
+    fn layout(stride: i32) -> [BufferLayout; 1] {
+        [BufferLayout { stride, ..Default::default() }]
+    }
+
+    #[test]
+    fn attributes_are_read_with_or_without_precision() {
+        assert_eq!(declared_attributes(VERTEX),
+            Ok(vec![("pos", "vec4"), ("colour", "vec4"), ("uv", "vec2")]));
+        assert_eq!(declared_attributes("attribute vec4;"),
+            Err("can't read attribute \"attribute vec4;\"".to_string()));
+    }
+
+    #[test]
+    fn vectors_read_formats_no_wider_than_them() {
+        assert!(readable("vec4", VertexFormat::Float3));
+        assert!(readable("vec3", VertexFormat::Float3));
+        assert!(!readable("vec2", VertexFormat::Float3));
+        assert!(readable("mat4", VertexFormat::Mat4));
+        assert!(!readable("vec4", VertexFormat::Mat4));
+        assert!(!readable("mat4", VertexFormat::Float4));
+        assert!(!readable("sampler2D", VertexFormat::Float1));
+    }
+
+    #[test]
+    fn matching_layouts_pass() {
+        assert_eq!(check_layout(VERTEX, &layout(36), &attributes()), Ok(()));
+        assert_eq!(check_layout(VERTEX, &layout(0), &attributes()), Ok(()));
+    }
+
+    #[test]
+    fn stride_mismatches_are_errors() {
+        assert_eq!(check_layout(VERTEX, &layout(48), &attributes()),
+            Err("buffer 0 has stride 48 but its attributes take 36 bytes".to_string()));
+    }
+
+    #[test]
+    fn attributes_missing_from_the_layout_are_errors() {
+        let mut attributes = attributes();
+        attributes.pop();
+        assert_eq!(check_layout(VERTEX, &layout(28), &attributes),
+            Err("shader attribute uv is not in the layout".to_string()));
+    }
+
+    #[test]
+    fn attributes_of_the_wrong_type_are_errors() {
+        let mut attributes = attributes();
+        attributes[2] = VertexAttribute::new("uv", VertexFormat::Float3);
+        assert_eq!(check_layout(VERTEX, &layout(40), &attributes),
+            Err("shader attribute uv is a vec2 but the layout gives Float3".to_string()));
+    }
+}
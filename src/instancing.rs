@@ -0,0 +1,291 @@
+use miniquad::*;
+use glam::{Vec4, Mat4};
+use mq_test::backend::{Handles, Pod};
+use mq_test::render_queue::{DrawItem, RenderQueue, State};
+use mq_test::gpu::{self, OwnedBuffer};
+use mq_test::mesh::Vertex;
+use crate::objects::{Object, ColouredObject};
+use crate::meshes::{MeshId, MeshRegistry};
+use crate::main_pipe::normal_matrix;
+
+// instances uploaded per draw; more than this are drawn in several goes
+pub const MAX_INSTANCES: usize = 1024;
+
+// per-instance vertex data. every instanced shader reads what it needs
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub model: Mat4,
+    pub normal_matrix: Mat4,
+    pub colour: Vec4,
+}
+
+unsafe impl Pod for Instance {}
+
+impl Instance {
+    pub fn new(model: Mat4, colour: Vec4) -> Instance {
+        Instance {
+            model,
+            normal_matrix: normal_matrix(model),
+            colour,
+        }
+    }
+}
+
+// the instance attributes in the order they are laid out in Instance
+fn attributes_for_instances() -> [VertexAttribute; 3] {
+    [
+        VertexAttribute::with_buffer("inst_model", VertexFormat::Mat4, 1),
+        VertexAttribute::with_buffer("inst_normal_matrix", VertexFormat::Mat4, 1),
+        VertexAttribute::with_buffer("inst_colour", VertexFormat::Float4, 1),
+    ]
+}
+
+// a depth tested pipeline reading mesh vertices from the first buffer and
+// instances from the second, checked against vertex_source
+pub fn pipeline(ctx: &mut Context, vertex_source: &str,
+    shader: Shader) -> Result<Pipeline, String> {
+    let mut attributes = Vertex::attributes().to_vec();
+    attributes.extend(attributes_for_instances());
+    gpu::checked_pipeline(
+        ctx,
+        vertex_source,
+        &[
+            Vertex::layout(),
+            BufferLayout {
+                stride: std::mem::size_of::<Instance>() as i32,
+                step_func: VertexStep::PerInstance,
+                ..Default::default()
+            },
+        ],
+        &attributes,
+        shader,
+        PipelineParams {
+            depth_test: Comparison::LessOrEqual,
+            depth_write: true,
+            ..Default::default()
+        },
+    )
+}
+
+// every instance of one mesh
+pub struct Batch {
+    pub mesh: MeshId,
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Default)]
+pub struct Batches {
+    pub batches: Vec<Batch>,
+}
+
+impl Batches {
+    pub fn new() -> Batches {
+        Batches::default()
+    }
+
+    pub fn push(&mut self, obj: &Object, colour: Vec4) {
+        let instance = Instance::new(obj.model, colour);
+        match self.batches.iter_mut().find(|b| b.mesh == obj.mesh) {
+            Some(batch) => batch.instances.push(instance),
+            None => self.batches.push(Batch {
+                mesh: obj.mesh,
+                instances: vec![instance],
+            }),
+        }
+    }
+
+    pub fn objects(objects: &[Object], colour: Vec4) -> Batches {
+        let mut batches = Batches::new();
+        for obj in objects.iter() {
+            batches.push(obj, colour);
+        }
+        batches
+    }
+
+    pub fn coloured_objects(objects: &[ColouredObject]) -> Batches {
+        let mut batches = Batches::new();
+        for cobj in objects.iter() {
+            batches.push(&cobj.object, cobj.colour);
+        }
+        batches
+    }
+}
+
+// a stream buffer the instance data is uploaded to before each draw
+pub struct InstanceBuffer {
+    buffer: OwnedBuffer,
+}
+
+impl InstanceBuffer {
+    pub fn new(ctx: &mut Context) -> InstanceBuffer {
+        let buffer = Buffer::stream(
+            ctx,
+            BufferType::VertexBuffer,
+            MAX_INSTANCES * std::mem::size_of::<Instance>(),
+        );
+        InstanceBuffer {
+            buffer: OwnedBuffer::new(buffer, "instances"),
+        }
+    }
+
+    // mesh bindings with the instance buffer added as the second vertex buffer
+    pub fn bind(&self, mesh: &Bindings) -> Bindings {
+        let mut bind = mesh.clone();
+        bind.vertex_buffers.truncate(1);
+        bind.vertex_buffers.push(*self.buffer);
+        bind
+    }
+
+    pub fn buffer(&self) -> Buffer {
+        *self.buffer
+    }
+
+    // states' bindings should come from bind(), one per mesh page
+    pub fn submit<U: Pod>(&self, queue: &mut RenderQueue, states: &[State],
+        meshes: &MeshRegistry, batches: Batches, view_proj: Mat4, uniforms: &U) {
+        submit(queue, states, self.buffer(), meshes, batches, view_proj, uniforms);
+    }
+}
+
+// clip w of an instance's origin, its distance in front of the eye as the
+// unbatched draws measure it
+fn depth(view_proj: Mat4, instance: &Instance) -> f32 {
+    view_proj.row(3).dot(instance.model.w_axis)
+}
+
+// one draw per mesh, or more if it has over MAX_INSTANCES instances, with
+// its instances uploaded to buffer first. instances are sorted front to
+// back as view_proj sees them and each draw is queued at the depth of its
+// nearest, so the queue's ordering still helps the depth test. states has
+// one for each mesh page
+pub fn submit<H: Handles, U: Pod>(queue: &mut RenderQueue<H>, states: &[State],
+    buffer: H::Buffer, meshes: &MeshRegistry, mut batches: Batches, view_proj: Mat4,
+    uniforms: &U) {
+    for batch in batches.batches.iter_mut() {
+        batch.instances.sort_by(|a, b| depth(view_proj, a)
+            .partial_cmp(&depth(view_proj, b))
+            .unwrap_or(std::cmp::Ordering::Equal));
+        let range = meshes.get(batch.mesh);
+        for chunk in batch.instances.chunks(MAX_INSTANCES) {
+            queue.submit(
+                DrawItem::new(states[range.page], range.start, range.count, uniforms,
+                    depth(view_proj, &chunk[0]))
+                    .with_instances(buffer, chunk));
+        }
+    }
+}
+
+// flat colour per instance, used by the coloured and glow passes
+pub const COLOURED_VERTEX: &str = r#"#version 100
+attribute vec4 pos;
+attribute mat4 inst_model;
+attribute vec4 inst_colour;
+
+varying lowp vec4 vcolour;
+
+uniform mat4 mvp;
+
+void main() {
+    gl_Position = mvp * inst_model * pos;
+    vcolour = inst_colour;
+}
+"#;
+
+pub const COLOURED_FRAGMENT: &str = r#"#version 100
+
+precision mediump float;
+
+varying lowp vec4 vcolour;
+
+void main() {
+    gl_FragColor = vcolour;
+}
+"#;
+
+pub fn coloured_meta() -> ShaderMeta {
+    ShaderMeta {
+        images: vec![],
+        uniforms: UniformBlockLayout {
+            uniforms: vec![
+                UniformDesc::new("mvp", UniformType::Mat4),
+            ]
+        },
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ColouredUniforms {
+    pub mvp: glam::Mat4,
+}
+
+unsafe impl Pod for ColouredUniforms {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec3;
+    use mq_test::backend::{Backend, Ids, Recorder};
+    use crate::meshes;
+
+    fn at(mesh: &str, z: f32) -> Object {
+        Object {
+            model: Mat4::from_translation(vec3(0.0, 0.0, z)),
+            mesh: meshes::builtin(mesh).unwrap(),
+        }
+    }
+
+    #[test]
+    fn batches_are_drawn_front_to_back() {
+        let meshes = MeshRegistry::new();
+        // looking down -z, so further objects have lower z
+        let view_proj = Mat4::perspective_rh_gl(1.0, 1.0, 0.1, 100.0);
+        let objects = [at("cube", -8.0), at("cube", -2.0), at("sphere", -1.0),
+            at("cube", -5.0)];
+        let mut queue = RenderQueue::<Ids>::new();
+        let state = queue.state(&0, &0);
+        submit(&mut queue, &[state], 0, &meshes, Batches::objects(&objects, Vec4::ONE),
+            view_proj, &());
+        let mut gfx = Recorder::<Ids>::new();
+        gfx.begin_pass(None, PassAction::Nothing);
+        queue.flush(&mut gfx);
+        gfx.end_render_pass();
+
+        // the sphere's batch holds the nearest instance so goes first
+        let sphere = meshes.get(meshes::builtin("sphere").unwrap());
+        assert_eq!(gfx.draws[0].base_element, sphere.start);
+        // then the cubes, nearest first. z of the translation is the 15th
+        // float of each instance
+        let cubes: Vec<f32> = gfx.uploads[1]
+            .chunks(std::mem::size_of::<Instance>())
+            .map(|c| f32::from_ne_bytes([c[56], c[57], c[58], c[59]]))
+            .collect();
+        assert_eq!(cubes, vec![-2.0, -5.0, -8.0]);
+    }
+
+    #[test]
+    fn batches_bind_the_page_their_mesh_is_in() {
+        let mut meshes = MeshRegistry::new();
+        // a whole page of vertices, so it can't share with the built in meshes
+        let big = meshes.add(&mq_test::primitives::plane(255).unwrap()).unwrap();
+        assert_eq!(meshes.get(big).page, 1);
+        let objects = [at("cube", -2.0), Object { mesh: big, ..at("cube", -3.0) }];
+        let mut queue = RenderQueue::<Ids>::new();
+        let pipeline = queue.pipeline(&0);
+        let states = queue.states(pipeline, &[10, 11]);
+        submit(&mut queue, &states, 0, &meshes, Batches::objects(&objects, Vec4::ONE),
+            Mat4::IDENTITY, &());
+        let mut gfx = Recorder::<Ids>::new();
+        gfx.begin_pass(None, PassAction::Nothing);
+        queue.flush(&mut gfx);
+        gfx.end_render_pass();
+
+        let mut drawn: Vec<(Option<usize>, i32)> = gfx.draws.iter()
+            .map(|d| (d.bindings, d.base_element))
+            .collect();
+        drawn.sort();
+        let cube = meshes.get(meshes::builtin("cube").unwrap());
+        assert_eq!(drawn, vec![(Some(10), cube.start), (Some(11), 0)]);
+    }
+}
@@ -1,3 +1,13 @@
+pub mod backend;
+pub mod clock;
+pub mod gpu;
+pub mod mesh;
+pub mod noise;
+pub mod primitives;
+pub mod random;
+pub mod render_queue;
+pub mod wavefront;
+
 pub fn quad_verts() -> (&'static[f32], &'static[u16]) {
     #[rustfmt::skip]
     let vertices: &[f32] = &[
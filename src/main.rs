@@ -1,271 +1,488 @@
+use std::cell::RefCell;
 use miniquad::*;
 
-use glam::{vec3, Vec3, Mat4, /*EulerRot*/};
+use std::path::{Path, PathBuf};
+use glam::{vec3, vec4, const_vec4, Vec3, Vec4, Mat4, /*EulerRot*/};
 
+mod blur;
 mod blur_pipe;
 mod blur_shadow_pipe;
 mod main_pipe;
 mod shadow_pipe;
 mod glow_pipe;
 mod objects;
+mod render_graph;
+mod post_node;
+mod instancing;
+mod culling;
+mod target;
+mod terrain;
+mod rules;
+mod entities;
+mod scene;
+mod export;
+mod collision;
+mod gltf_import;
+mod meshes;
 
 use main_pipe::MainPipe;
 use shadow_pipe::ShadowPipe;
 use glow_pipe::GlowPipe;
-use objects::{Object, ColouredObject};
-use mq_test::quad_verts;
-
-struct PipeBind {
-    pipe: Pipeline,
-    bind: Bindings
+use objects::{Object, ColouredObject, Chunk, ChunkManager};
+use meshes::MeshRegistry;
+use rules::{Rules, RulesFile};
+use entities::Entities;
+use scene::{Scene, Generator};
+use collision::{Sphere, Ray};
+use render_graph::{Frame, RenderGraph};
+use culling::CullStats;
+use mq_test::render_queue::QueueStats;
+use post_node::{PostNode, Output, quad_bindings};
+use target::Size;
+use mq_test::gpu::{self, OwnedBuffer};
+use mq_test::clock::Clock;
+use mq_test::wavefront::{self, Normals};
+use mq_test::mesh::Mesh;
+
+// simulation steps per second
+const STEP_RATE: f64 = 60.0;
+// forward speed in units per second
+const SPEED: f32 = 6.0;
+// how close the camera can get to anything
+const CAMERA_RADIUS: f32 = 0.15;
+// picked objects are drawn over in this colour, a little larger
+const HIGHLIGHT: Vec4 = const_vec4!([1.0, 1.0, 0.6, 1.0]);
+const HIGHLIGHT_SCALE: f32 = 1.05;
+// time scale while slow motion is on
+const SLOW_MOTION: f64 = 0.25;
+// read at start and whenever it changes
+const RULES_PATH: &str = "rules.toml";
+// where F3 saves the scene and F4 exports what's drawn
+const SAVE_PATH: &str = "saved_scene.toml";
+const EXPORT_PATH: &str = "export.obj";
+
+// what was last clicked on. a grid cell's object is kept as it was, the
+// rest are followed by index as they move
+#[derive(Clone, Copy)]
+enum Picked {
+    Cell(Object),
+    Object(usize),
+    Coloured(usize),
 }
 
 struct Stage {
-    shadow_map: ShadowPipe,
-    shadow_map_bind: Bindings,
-    main: MainPipe,
-    main_bind: Bindings,
-    glow: GlowPipe,
-    glow_bind: Bindings,
-    copy: PipeBind,
-    _depth_view: PipeBind,
-    glow_blend: PipeBind,
+    scene: Scene,
+    graph: RenderGraph,
+    _buffers: Vec<OwnedBuffer>,
+    // the built in meshes and anything imported
+    meshes: MeshRegistry,
+    rules: Rules,
+    rules_file: RulesFile,
+    chunks: ChunkManager,
+    // the scatter layers
+    entities: Entities,
+    // the scene's own objects
+    scene_objects: Vec<Object>,
+    scene_coloured_objects: Vec<ColouredObject>,
+    // everything not in a chunk
     objects: Vec<Object>,
     coloured_objects: Vec<ColouredObject>,
+    clock: Clock,
+    // position at the last two simulation steps, and drawn between them
+    prev_pos: Vec3,
     pos: Vec3,
+    view_pos: Vec3,
+    instanced: bool,
+    // stop the camera running through objects
+    collide: bool,
+    picked: Option<Picked>,
+    // proj * view * scene model of the last frame, to pick with
+    pick_view_proj: Mat4,
+    // export the next frame drawn here
+    export_path: Option<PathBuf>,
+    camera_cull: CullStats,
+    light_cull: CullStats,
+    queue_stats: QueueStats,
 }
 
-fn copy_pipe(ctx: &mut Context, tex:Texture) -> PipeBind {
-    let (vertices, indices) = quad_verts();
-    let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices);
-    let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &indices);
-
-    let bind = Bindings {
-        vertex_buffers: vec![vertex_buffer],
-        index_buffer: index_buffer,
-        images: vec![tex],
-    };
+impl Stage {
+    pub fn new(ctx: &mut Context, scene: Scene, meshes: MeshRegistry,
+        imported: (Vec<Object>, Vec<ColouredObject>), export_path: Option<PathBuf>) -> Stage {
+        let binds = meshes.bindings(ctx);
+        let quad = quad_bindings(ctx);
+        let mut buffers: Vec<OwnedBuffer> = binds.iter()
+            .flat_map(|bind| gpu::own_buffers(bind, "meshes"))
+            .collect();
+        buffers.extend(gpu::own_buffers(&quad, "quad"));
+
+        let mut graph = RenderGraph::new();
+        graph.add(ShadowPipe::new(ctx, binds.clone(), &quad, Size::Fixed(512, 512)));
+        graph.add(MainPipe::new(ctx, binds.clone(), Size::Screen(1.0)));
+        graph.add(GlowPipe::new(ctx, binds, &quad, Size::Screen(0.25)));
+        graph.add(PostNode::new(ctx, &quad, glow_blend_shader::FRAGMENT,
+            glow_blend_shader::meta(), vec!["scene", "glow"], Output::Screen, ()));
+        //graph.add(PostNode::new(ctx, &quad, depth_view_shader::FRAGMENT,
+        //    depth_view_shader::meta(), vec!["shadow_map"], Output::Screen, ()));
+        //graph.add(PostNode::new(ctx, &quad, copy_to_screen_shader::FRAGMENT,
+        //    copy_to_screen_shader::meta(), vec!["glow"], Output::Screen, ()));
+        graph.build().unwrap();
+
+        let mut rules_file = RulesFile::new(RULES_PATH);
+        let rules = match rules_file.poll(0.0) {
+            Some(Ok(rules)) => rules,
+            Some(Err(e)) => {
+                eprintln!("{}: {}", RULES_PATH, e);
+                Rules::builtin()
+            }
+            None => Rules::builtin(),
+        };
+
+        let seed = scene.seed;
+        let pos = Vec3::from(scene.offset);
+        let mut entities = Entities::new();
+        if scene.runs(Generator::Scatter) {
+            objects::spawn_scatter(seed, &rules, pos, &mut entities);
+        }
+        let (mut scene_objects, mut scene_coloured_objects) = scene.objects();
+        scene_objects.extend(imported.0);
+        scene_coloured_objects.extend(imported.1);
 
-    let shader = Shader::new(
-        ctx,
-        copy_to_screen_shader::VERTEX,
-        copy_to_screen_shader::FRAGMENT,
-        copy_to_screen_shader::meta(),
-    )
-    .unwrap();
-
-    let pipe = Pipeline::new(
-        ctx,
-        &[BufferLayout::default()],
-        &[
-            VertexAttribute::new("pos", VertexFormat::Float2),
-            VertexAttribute::new("uv", VertexFormat::Float2),
-        ],
-        shader,
-    );
-
-    PipeBind {
-        pipe,
-        bind
+        Stage {
+            scene,
+            graph,
+            _buffers: buffers,
+            meshes,
+            chunks: ChunkManager::new(seed, &rules),
+            rules,
+            rules_file,
+            entities,
+            scene_objects,
+            scene_coloured_objects,
+            objects: vec![],
+            coloured_objects: vec![],
+            clock: Clock::new(STEP_RATE),
+            prev_pos: pos,
+            pos,
+            view_pos: pos,
+            instanced: true,
+            collide: true,
+            picked: None,
+            pick_view_proj: Mat4::IDENTITY,
+            export_path,
+            camera_cull: CullStats::default(),
+            light_cull: CullStats::default(),
+            queue_stats: QueueStats::default(),
+        }
     }
-}
-
-fn glow_blend_pipe(ctx: &mut Context,
-        main:Texture, glow:Texture) -> PipeBind {
-    let (vertices, indices) = quad_verts();
-    let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices);
-    let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &indices);
 
-    let bind = Bindings {
-        vertex_buffers: vec![vertex_buffer],
-        index_buffer: index_buffer,
-        images: vec![main, glow],
-    };
-
-    let shader = Shader::new(
-        ctx,
-        glow_blend_shader::VERTEX,
-        glow_blend_shader::FRAGMENT,
-        glow_blend_shader::meta(),
-    )
-    .unwrap();
-
-    let pipe = Pipeline::new(
-        ctx,
-        &[BufferLayout::default()],
-        &[
-            VertexAttribute::new("pos", VertexFormat::Float2),
-            VertexAttribute::new("uv", VertexFormat::Float2),
-        ],
-        shader,
-    );
-
-    PipeBind {
-        pipe,
-        bind
+    // pos moved on by step, unless that runs the camera into something in
+    // which case it slides along it. the world is drawn offset by pos, so
+    // the camera is at eye - pos in it and moves the opposite way
+    fn move_camera(&self, step: Vec3) -> Vec3 {
+        let eye = Vec3::from(self.scene.camera.eye);
+        let camera = Sphere {
+            centre: eye - self.pos,
+            radius: CAMERA_RADIUS,
+        };
+        // the scene's objects and the entities where the last step left them
+        let (mut others, coloured) = self.entities.objects(1.0);
+        others.extend(coloured.iter().map(|c| c.object));
+        others.extend_from_slice(&self.scene_objects);
+        others.extend(self.scene_coloured_objects.iter().map(|c| c.object));
+        let obstacles = collision::obstacles(&self.chunks, &others,
+            &self.meshes, camera.centre, CAMERA_RADIUS + step.length());
+        eye - collision::slide(&obstacles, &camera, -step)
     }
 }
 
-fn depth_view_pipe(ctx: &mut Context, tex:Texture) -> PipeBind {
-    let (vertices, indices) = quad_verts();
-    let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices);
-    let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &indices);
+impl EventHandler for Stage {
+    fn update(&mut self, ctx: &mut Context) {
+        match self.rules_file.poll(date::now()) {
+            Some(Ok(rules)) => {
+                // regenerate everything from the new rules
+                self.chunks = ChunkManager::new(self.scene.seed, &rules);
+                self.entities = Entities::new();
+                self.picked = None;
+                if self.scene.runs(Generator::Scatter) {
+                    objects::spawn_scatter(self.scene.seed, &rules, self.pos,
+                        &mut self.entities);
+                }
+                self.rules = rules;
+            }
+            Some(Err(e)) => eprintln!("{}: {}", self.rules_file.path().display(), e),
+            None => {}
+        }
 
-    let bind = Bindings {
-        vertex_buffers: vec![vertex_buffer],
-        index_buffer: index_buffer,
-        images: vec![tex],
-    };
+        let steps = self.clock.tick(date::now());
+        let dt = self.clock.dt();
+        for _ in 0..steps {
+            self.prev_pos = self.pos;
+            let step = vec3(0., 0., SPEED * dt);
+            self.pos = if self.collide { self.move_camera(step) } else { self.pos + step };
+            let seed = self.scene.seed;
+            self.entities.step(dt, -self.pos, |x, z| terrain::height(seed, x, z));
+        }
+        self.view_pos = self.prev_pos.lerp(self.pos, self.clock.alpha());
+        // the scene is drawn offset by pos, so the camera is over -pos
+        if self.scene.runs(Generator::Chunks) {
+            self.chunks.update(ctx, &self.meshes, -self.view_pos);
+        }
+        let (mut objects, mut coloured_objects) = self.entities.objects(self.clock.alpha());
+        objects.extend_from_slice(&self.scene_objects);
+        coloured_objects.extend_from_slice(&self.scene_coloured_objects);
+        self.objects = objects;
+        self.coloured_objects = coloured_objects;
+    }
 
-    let shader = Shader::new(
-        ctx,
-        depth_view_shader::VERTEX,
-        depth_view_shader::FRAGMENT,
-        depth_view_shader::meta(),
-    )
-    .unwrap();
-
-    let pipe = Pipeline::new(
-        ctx,
-        &[BufferLayout::default()],
-        &[
-            VertexAttribute::new("pos", VertexFormat::Float2),
-            VertexAttribute::new("uv", VertexFormat::Float2),
-        ],
-        shader,
-    );
-
-    PipeBind {
-        pipe,
-        bind
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.graph.resize(ctx, width, height);
     }
-}
 
-impl Stage {
-    pub fn new(ctx: &mut Context) -> Stage {
-        let bind = objects::cube_bindings(ctx);
+    fn key_down_event(&mut self, _ctx: &mut Context, keycode: KeyCode,
+        _keymods: KeyMods, _repeat: bool) {
+        match keycode {
+            KeyCode::F1 => {
+                println!("seed {}", self.scene.seed);
+                print!("{}", gpu::report());
+                println!("camera {:?}", self.camera_cull);
+                println!("light {:?}", self.light_cull);
+                println!("{:?}", self.queue_stats);
+                println!("{} loaded chunks", self.chunks.len());
+                println!("{} entities", self.entities.len());
+            }
+            KeyCode::F2 => self.instanced = !self.instanced,
+            KeyCode::F3 => {
+                let scene = Scene {
+                    offset: self.pos.to_array(),
+                    ..self.scene.clone()
+                };
+                match scene.save(Path::new(SAVE_PATH)) {
+                    Ok(()) => println!("saved {}", SAVE_PATH),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+            KeyCode::F4 => self.export_path = Some(PathBuf::from(EXPORT_PATH)),
+            KeyCode::C => self.collide = !self.collide,
+            KeyCode::P => self.clock.set_paused(!self.clock.paused()),
+            KeyCode::N => self.clock.single_step(),
+            KeyCode::M => {
+                let scale = if self.clock.time_scale() < 1.0 { 1.0 } else { SLOW_MOTION };
+                self.clock.set_time_scale(scale);
+            }
+            _ => {}
+        }
+    }
 
-        let shadow_map = ShadowPipe::new(ctx);
-        let shadow_map_bind = bind.clone();
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton,
+        x: f32, y: f32) {
+        if button != MouseButton::Left {
+            return;
+        }
+        let (width, height) = ctx.screen_size();
+        let ray = Ray::from_screen(x, y, width, height, self.pick_view_proj);
+        let cells: Vec<Object> = self.chunks.chunks()
+            .flat_map(|c| c.objects.iter()
+                .chain(c.coloured_objects.iter().map(|cobj| &cobj.object)))
+            .copied()
+            .collect();
+        let hits = [
+            collision::pick(&ray, cells.iter(), &self.meshes)
+                .map(|hit| (hit, Picked::Cell(cells[hit.index]))),
+            collision::pick(&ray, self.objects.iter(), &self.meshes)
+                .map(|hit| (hit, Picked::Object(hit.index))),
+            collision::pick(&ray, self.coloured_objects.iter().map(|c| &c.object),
+                &self.meshes)
+                .map(|hit| (hit, Picked::Coloured(hit.index))),
+        ];
+        let nearest = hits.iter().flatten()
+            .min_by(|a, b| a.0.distance.partial_cmp(&b.0.distance)
+                .unwrap_or(std::cmp::Ordering::Equal));
+
+        self.picked = nearest.map(|(_, picked)| *picked);
+        match nearest {
+            Some((hit, picked)) => {
+                let obj = match picked {
+                    Picked::Cell(obj) => *obj,
+                    Picked::Object(i) => self.objects[*i],
+                    Picked::Coloured(i) => self.coloured_objects[*i].object,
+                };
+                let (scale, rotation, position) = obj.model.to_scale_rotation_translation();
+                match picked {
+                    Picked::Cell(_) => println!("picked the object in cell {}, {}",
+                        position.x.round(), position.z.round()),
+                    Picked::Object(i) => println!("picked object {}", i),
+                    Picked::Coloured(i) => println!("picked coloured object {}", i),
+                }
+                println!("  {:.2} away, at {:?} scale {:?} rotation {:?}",
+                    hit.distance, position, scale, rotation);
+            }
+            None => println!("picked nothing"),
+        }
+    }
 
-        let main = MainPipe::new(ctx);
-        let mut main_bind = bind.clone();
-        main_bind.images.push(shadow_map.get_output());
+    fn draw(&mut self, ctx: &mut Context) {
+        let (width, height) = ctx.screen_size();
+        let proj = self.scene.camera.proj(width / height);
+        let view = self.scene.camera.view();
+        let view_proj = proj * view;
 
-        let glow = GlowPipe::new(ctx);
-        let glow_bind = bind.clone();
+        let light_proj = self.scene.light.proj();
+        let light_view = self.scene.light.view();
+        let light_pos_view = view * Vec3::from(self.scene.light.position).extend(1.0);
+        let model = Mat4::from_translation(self.view_pos);
+
+        self.camera_cull = CullStats::default();
+        self.light_cull = CullStats::default();
+        let meshes = &self.meshes;
+        let mut objects = culling::cull_chunks(&self.chunks, meshes, view_proj * model,
+            &mut self.camera_cull);
+        objects.extend(culling::cull(&self.objects, meshes, view_proj * model,
+            &mut self.camera_cull));
+        let terrain = culling::cull_terrain(&self.chunks, view_proj * model,
+            &mut self.camera_cull);
+        let mut coloured_objects = culling::cull_chunks_coloured(&self.chunks, meshes,
+            view_proj * model, &mut self.camera_cull);
+        coloured_objects.extend(culling::cull_coloured(&self.coloured_objects, meshes,
+            view_proj * model, &mut self.camera_cull));
+        let mut shadow_casters = culling::cull_chunks(&self.chunks, meshes,
+            light_proj * light_view * model, &mut self.light_cull);
+        shadow_casters.extend(culling::cull(&self.objects, meshes,
+            light_proj * light_view * model, &mut self.light_cull));
+        let shadow_terrain = culling::cull_terrain(&self.chunks,
+            light_proj * light_view * model, &mut self.light_cull);
+
+        // everything loaded goes in the export, not just what's in view
+        if let Some(path) = self.export_path.take() {
+            let chunks: Vec<&Chunk> = self.chunks.chunks().collect();
+            let all_objects: Vec<Object> = chunks.iter()
+                .flat_map(|c| c.objects.iter())
+                .chain(self.objects.iter())
+                .copied()
+                .collect();
+            let all_coloured: Vec<ColouredObject> = chunks.iter()
+                .flat_map(|c| c.coloured_objects.iter())
+                .chain(self.coloured_objects.iter())
+                .copied()
+                .collect();
+            let terrain: Vec<&Mesh> = chunks.iter().map(|c| &c.terrain.mesh).collect();
+            match export::write_obj(&path, meshes, &all_objects, &all_coloured,
+                &terrain, model) {
+                Ok(()) => println!("exported {}", path.display()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
 
-        let copy = copy_pipe(ctx, main.get_output());
-        let glow_blend = glow_blend_pipe(ctx,
-            main.get_output(), glow.get_output());
-        let _depth_view = depth_view_pipe(ctx, shadow_map.get_output());
- 
-        Stage {
-            shadow_map,
-            shadow_map_bind,
-            main,
-            main_bind,
-            glow,
-            glow_bind,
-            copy,
-            _depth_view,
-            glow_blend,
-            objects: vec![],
-            coloured_objects: vec![],
-            pos: vec3(0., 0., 0.)
+        // the highlight shell is drawn but not part of the scene, so it's
+        // added after the export
+        let highlight = match self.picked {
+            Some(Picked::Cell(obj)) => Some(obj),
+            Some(Picked::Object(i)) => self.objects.get(i).copied(),
+            Some(Picked::Coloured(i)) => self.coloured_objects.get(i).map(|c| c.object),
+            None => None,
+        };
+        if let Some(obj) = highlight {
+            coloured_objects.push(ColouredObject {
+                object: Object {
+                    model: obj.model * Mat4::from_scale(Vec3::splat(HIGHLIGHT_SCALE)),
+                    ..obj
+                },
+                colour: HIGHLIGHT,
+            });
         }
+        self.pick_view_proj = view_proj * model;
+
+        gpu::collect(ctx);
+        let frame = Frame {
+            meshes,
+            objects: &objects,
+            coloured_objects: &coloured_objects,
+            terrain: &terrain,
+            shadow_casters: &shadow_casters,
+            shadow_terrain: &shadow_terrain,
+            scene_model: model,
+            view_proj,
+            light_pos: light_pos_view,
+            light_view,
+            light_proj,
+            instanced: self.instanced,
+            stats: RefCell::new(QueueStats::default()),
+        };
+        self.graph.draw(ctx, &frame);
+        self.queue_stats = frame.stats.into_inner();
+        ctx.commit_frame();
     }
 }
 
-impl EventHandler for Stage {
-    fn update(&mut self, _ctx: &mut Context) {
-        self.pos.z += 0.1;
-        self.objects = objects::cubes(self.pos);
-        self.coloured_objects = objects::coloured_cubes(self.pos);
-    }
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
 
-    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
-        self.main.resize(ctx, width, height);
-        self.copy.bind.images[0] = self.main.get_output();
-        self.glow_blend.bind.images[0] = self.main.get_output();
+// the value following name on the command line
+fn arg(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == name)?;
+    match args.get(i + 1) {
+        Some(value) => Some(value.clone()),
+        None => exit_with(&format!("{} needs a value", name)),
     }
+}
 
-    fn draw(&mut self, ctx: &mut Context) {
-        let (width, height) = ctx.screen_size();
-        let proj = Mat4::perspective_rh_gl(60.0f32.to_radians(), width / height, 0.01, 20.0);
-        let view = Mat4::look_at_rh(
-            vec3(0.5, 0.125, 5.0),
-            vec3(0.5, 0.0, 0.0),
-            vec3(0.0, 1.0, 0.0),
-        );
-        let view_proj = proj * view;
+// --scene <file> loads a scene, otherwise everything is generated.
+// --seed <n> picks the world, the same seed always gives the same world
+fn scene_from_args() -> Scene {
+    let mut scene = match arg("--scene") {
+        Some(path) => Scene::load(Path::new(&path)).unwrap_or_else(|e| exit_with(&e)),
+        None => Scene::default(),
+    };
+    if let Some(seed) = arg("--seed") {
+        scene.seed = scene::parse_seed(&seed)
+            .unwrap_or_else(|e| exit_with(&format!("--seed: {}", e)));
+    }
+    scene
+}
 
-        let light_pos = vec3(-100.0, 100.0, 100.0);
-        let light_range = (136.0, 200.0);
-        let light_proj = Mat4::perspective_rh_gl(10.0f32.to_radians(), 1.0,
-            light_range.0, light_range.1);
-        let light_view = Mat4::look_at_rh(
-            light_pos,
-            vec3(0.0, 0.0, 0.0),
-            vec3(0.0, 1.0, 0.0),
-        );
-        let light_pos_view = view * light_pos.extend(1.0);
-        let model = Mat4::from_translation(self.pos);
-
-        self.shadow_map.draw(ctx, &self.shadow_map_bind,
-            &self.objects, 
-            &model, &light_view, &light_proj);
-
-        self.main.draw(ctx, &self.main_bind,
-            &self.objects, 
-            &self.coloured_objects, 
-            &model, light_pos_view, &view_proj, &light_view,
-            &light_proj);
-
-        self.glow.draw(ctx, &self.glow_bind,
-            &self.objects, 
-            &self.coloured_objects, 
-            &model, &view_proj);
-
-        let output = &self.glow_blend;
-        //let output = &self._depth_view;
-        //let output = &mut self.copy;
-        // output.bind.images[0] = self.glow.get_output();
-        // and the post-processing-pass, rendering a quad, using the
-        // previously rendered offscreen render-target as texture
-        ctx.begin_default_pass(PassAction::Nothing);
-        ctx.apply_pipeline(&output.pipe);
-        ctx.apply_bindings(&output.bind);
-        //ctx.apply_uniforms(&copy_to_screen_shader::Uniforms {
-        //    resolution: glam::vec2(w, h),
-        //});
-        ctx.draw(0, 6, 1);
-        ctx.end_render_pass();
-        ctx.commit_frame();
+// a gltf's objects, with its meshes added to meshes
+fn import_from(path: &Path, meshes: &mut MeshRegistry) -> (Vec<Object>, Vec<ColouredObject>) {
+    let import = gltf_import::load(path, meshes).unwrap_or_else(|e| exit_with(&e));
+    for warning in import.warnings.iter() {
+        eprintln!("{}: {}", path.display(), warning);
     }
+    (import.objects, import.coloured_objects)
+}
+
+// an obj's mesh added to meshes, and an object at the origin drawing it
+fn obj_from(path: &Path, meshes: &mut MeshRegistry) -> Object {
+    let mesh = wavefront::load(path, Normals::Smooth, vec4(1.0, 1.0, 1.0, 1.0))
+        .unwrap_or_else(|e| exit_with(&e));
+    let mesh = meshes.add(&mesh)
+        .unwrap_or_else(|e| exit_with(&format!("{}: {}", path.display(), e)));
+    Object { model: Mat4::IDENTITY, mesh }
 }
 
 fn main() {
-    miniquad::start(conf::Conf::default(), |mut ctx| {
-        UserData::owning(Stage::new(&mut ctx), ctx)
+    let scene = scene_from_args();
+    // --import <file.gltf> adds the objects in a gltf or glb to the scene
+    let mut meshes = MeshRegistry::new();
+    let mut imported = match arg("--import") {
+        Some(path) => import_from(Path::new(&path), &mut meshes),
+        None => (vec![], vec![]),
+    };
+    // --obj <file.obj> adds a wavefront mesh at the origin
+    if let Some(path) = arg("--obj") {
+        imported.0.push(obj_from(Path::new(&path), &mut meshes));
+    }
+    // --export <file.obj> writes the first frame drawn to an obj
+    let export_path = arg("--export").map(PathBuf::from);
+    miniquad::start(conf::Conf::default(), move |mut ctx| {
+        UserData::owning(Stage::new(&mut ctx, scene, meshes, imported, export_path), ctx)
     });
 }
 
+#[allow(dead_code)]
 mod copy_to_screen_shader {
     use miniquad::*;
 
-    pub const VERTEX: &str = r#"#version 100
-    attribute vec2 pos;
-    attribute vec2 uv;
-
-    varying lowp vec2 texcoord;
-
-    void main() {
-        gl_Position = vec4(pos, 0, 1);
-        texcoord = uv;
-    }
-    "#;
-
     pub const FRAGMENT: &str = r#"#version 100
     precision lowp float;
 
@@ -296,18 +513,6 @@ mod copy_to_screen_shader {
 mod glow_blend_shader {
     use miniquad::*;
 
-    pub const VERTEX: &str = r#"#version 100
-    attribute vec2 pos;
-    attribute vec2 uv;
-
-    varying lowp vec2 texcoord;
-
-    void main() {
-        gl_Position = vec4(pos, 0, 1);
-        texcoord = uv;
-    }
-    "#;
-
     pub const FRAGMENT: &str = r#"#version 100
     precision lowp float;
 
@@ -339,21 +544,10 @@ mod glow_blend_shader {
     }
 }
 
+#[allow(dead_code)]
 mod depth_view_shader {
     use miniquad::*;
 
-    pub const VERTEX: &str = r#"#version 100
-    attribute vec2 pos;
-    attribute vec2 uv;
-
-    varying lowp vec2 texcoord;
-
-    void main() {
-        gl_Position = vec4(pos, 0, 1);
-        texcoord = uv;
-    }
-    "#;
-
     pub const FRAGMENT: &str = r#"#version 100
     precision mediump float;
 
@@ -1,15 +1,26 @@
 use miniquad::*;
-use glam::{Vec4, Mat3, Mat4};
-use crate::objects::{Object, ColouredObject};
+use mq_test::backend::{Backend, Pod};
+use mq_test::gpu::{self, OwnedPipeline};
+use mq_test::mesh::Vertex;
+use mq_test::render_queue::{DrawItem, RenderQueue, State};
+use glam::{vec4, Mat3, Mat4};
+use crate::instancing::{self, Batches, InstanceBuffer};
+use crate::terrain;
+use crate::render_graph::{Frame, RenderNode};
+use crate::target::{Size, Target};
 
 pub struct MainPipe {
-    pass:RenderPass,
-    pipe:Pipeline,
-    coloured_pipe:Pipeline,
-    output:Texture
+    target:Target,
+    pipe:OwnedPipeline,
+    coloured_pipe:OwnedPipeline,
+    instanced_pipe:OwnedPipeline,
+    instanced_coloured_pipe:OwnedPipeline,
+    binds:Vec<Bindings>,
+    instanced_binds:Vec<Bindings>,
+    instances:InstanceBuffer,
 }
 
-fn normal_matrix(model:Mat4) -> Mat4 {
+pub fn normal_matrix(model:Mat4) -> Mat4 {
     // normal matrix calculation from
     // https://www.lighthouse3d.com/tutorials/glsl-12-tutorial/the-normal-matrix/ 
     Mat4::from_mat3(
@@ -19,27 +30,8 @@ fn normal_matrix(model:Mat4) -> Mat4 {
 }
 
 impl MainPipe {
-    pub fn new(ctx: &mut Context) -> MainPipe {
-        let (w, h) = ctx.screen_size();
-        let color_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: w as _,
-                height: h as _,
-                format: TextureFormat::RGBA8,
-                ..Default::default()
-            },
-        );
-        let depth_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: w as _,
-                height: h as _,
-                format: TextureFormat::Depth,
-                ..Default::default()
-            },
-        );
-        let pass = RenderPass::new(ctx, color_img, depth_img);
+    pub fn new(ctx: &mut Context, binds: Vec<Bindings>, size: Size) -> MainPipe {
+        let target = Target::new(ctx, "scene", size, TextureFormat::RGBA8, true);
 
         let shader = Shader::new(
             ctx,
@@ -49,24 +41,19 @@ impl MainPipe {
         )
         .unwrap();
 
-        let pipe = Pipeline::with_params(
+        let pipe = gpu::checked_pipeline(
             ctx,
-            &[BufferLayout {
-                stride: 48,
-                ..Default::default()
-            }],
-            &[
-                VertexAttribute::new("pos", VertexFormat::Float3),
-                VertexAttribute::new("color0", VertexFormat::Float4),
-                VertexAttribute::new("normal", VertexFormat::Float3),
-            ],
+            VERTEX,
+            &[Vertex::layout()],
+            &Vertex::attributes(),
             shader,
             PipelineParams {
                 depth_test: Comparison::LessOrEqual,
                 depth_write: true,
                 ..Default::default()
             },
-        );
+        )
+        .unwrap();
 
         let shader = Shader::new(
             ctx,
@@ -76,100 +63,167 @@ impl MainPipe {
         )
         .unwrap();
 
-        let coloured_pipe = Pipeline::with_params(
+        let coloured_pipe = gpu::checked_pipeline(
             ctx,
-            &[BufferLayout {
-                stride: 48,
-                ..Default::default()
-            }],
-            &[
-                VertexAttribute::new("pos", VertexFormat::Float3),
-            ],
+            COLOURED_VERTEX,
+            &[Vertex::layout()],
+            &Vertex::attributes(),
             shader,
             PipelineParams {
                 depth_test: Comparison::LessOrEqual,
                 depth_write: true,
                 ..Default::default()
             },
-        );
+        )
+        .unwrap();
+        let shader = Shader::new(
+            ctx,
+            INSTANCED_VERTEX,
+            FRAGMENT,
+            instanced_meta(),
+        )
+        .unwrap();
+
+        let instanced_pipe = instancing::pipeline(ctx, INSTANCED_VERTEX, shader)
+            .unwrap();
+
+        let shader = Shader::new(
+            ctx,
+            instancing::COLOURED_VERTEX,
+            instancing::COLOURED_FRAGMENT,
+            instancing::coloured_meta(),
+        )
+        .unwrap();
+
+        let instanced_coloured_pipe = instancing::pipeline(ctx,
+            instancing::COLOURED_VERTEX, shader).unwrap();
+        let instances = InstanceBuffer::new(ctx);
+        let instanced_binds = binds.iter().map(|b| instances.bind(b)).collect();
+
         MainPipe {
-            pass,
-            pipe,
-            coloured_pipe,
-            output: color_img
+            target,
+            pipe: OwnedPipeline::new(pipe, "scene"),
+            coloured_pipe: OwnedPipeline::new(coloured_pipe, "scene coloured"),
+            instanced_pipe: OwnedPipeline::new(instanced_pipe, "scene instanced"),
+            instanced_coloured_pipe: OwnedPipeline::new(instanced_coloured_pipe,
+                "scene coloured instanced"),
+            binds,
+            instanced_binds,
+            instances,
         }
     }
 
-    pub fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
-        let color_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: width as _,
-                height: height as _,
-                format: TextureFormat::RGBA8,
-                ..Default::default()
-            },
-        );
-        
-        let depth_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: width as _,
-                height: height as _,
-                format: TextureFormat::Depth,
-                ..Default::default()
-            },
-        );
+}
 
-        let pass = RenderPass::new(ctx, color_img, depth_img);
+impl RenderNode for MainPipe {
+    fn inputs(&self) -> &[&'static str] {
+        &["shadow_map"]
+    }
 
-        self.pass.delete(ctx);
-        self.pass = pass;
-        self.output = color_img;
+    fn outputs(&self) -> &[&'static str] {
+        &["scene"]
     }
 
-    pub fn draw(&self, ctx: &mut Context,
-        bind: &Bindings,
-        objects: &Vec<Object>,
-        coloured_objects: &Vec<ColouredObject>,
-        scene_model: &Mat4, light_pos: Vec4, view_proj: &Mat4, light_view: &Mat4,
-        light_proj: &Mat4) {
-        ctx.begin_pass(
-            self.pass,
+    fn set_input(&mut self, _slot: usize, tex: Texture) {
+        for bind in self.binds.iter_mut().chain(self.instanced_binds.iter_mut()) {
+            bind.images = vec![tex];
+        }
+    }
+
+    fn get_output(&self, _slot: usize) -> Option<Texture> {
+        Some(self.target.output())
+    }
+
+    fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.target.resize(ctx, width, height);
+    }
+
+    fn draw(&self, gfx: &mut dyn Backend, frame: &Frame) {
+        gfx.begin_pass(
+            Some(self.target.pass()),
             PassAction::clear_color(0.0, 0.0, 0.0, 0.0),
         );
-        ctx.apply_pipeline(&self.pipe);
-        ctx.apply_bindings(bind);
-        for obj in objects.iter() {
-            let model = *scene_model * obj.model;
-            let normal_matrix = normal_matrix(model);
-            ctx.apply_uniforms(&Uniforms {
-                model,
-                proj: *view_proj,
-                normal_matrix,
-                light_pos,
-                light_mv: *light_view * model,
-                light_proj: *light_proj,
-            });
-            ctx.draw(obj.start, obj.end, 1);
+        let mut queue: RenderQueue = RenderQueue::new();
+        // shared by the instanced objects and the terrain
+        let instanced = queue.pipeline(&self.instanced_pipe);
+        if frame.instanced {
+            let states = queue.states(instanced, &self.instanced_binds);
+            queue_instanced(&mut queue, &states, &self.instances, frame);
+            let pipeline = queue.pipeline(&self.instanced_coloured_pipe);
+            let states = queue.states(pipeline, &self.instanced_binds);
+            queue_coloured_instanced(&mut queue, &states, &self.instances, frame);
+        } else {
+            let pipeline = queue.pipeline(&self.pipe);
+            let states = queue.states(pipeline, &self.binds);
+            queue_objects(&mut queue, &states, frame);
+            let pipeline = queue.pipeline(&self.coloured_pipe);
+            let states = queue.states(pipeline, &self.binds);
+            queue_coloured_objects(&mut queue, &states, frame);
         }
-        ctx.apply_pipeline(&self.coloured_pipe);
-        ctx.apply_bindings(bind);
-        for cobj in coloured_objects.iter() {
-            ctx.apply_uniforms(&ColouredUniforms {
-                mvp: *view_proj * *scene_model * cobj.object.model,
+        terrain::queue_patches(&mut queue, instanced, &self.instanced_binds[0],
+            frame.terrain, vec4(1., 1., 1., 1.), &instanced_uniforms(frame));
+        frame.stats.borrow_mut().add(queue.flush(gfx));
+        gfx.end_render_pass();
+    }
+}
+
+// lit, shadowed objects for the main pipeline
+pub fn queue_objects(queue: &mut RenderQueue, states: &[State], frame: &Frame) {
+    for obj in frame.objects.iter() {
+        let model = frame.scene_model * obj.model;
+        let normal_matrix = normal_matrix(model);
+        let depth = (frame.view_proj * model).w_axis.w;
+        let range = frame.meshes.get(obj.mesh);
+        queue.submit(DrawItem::new(states[range.page], range.start, range.count, &Uniforms {
+            model,
+            proj: frame.view_proj,
+            normal_matrix,
+            light_pos: frame.light_pos,
+            light_mv: frame.light_view * model,
+            light_proj: frame.light_proj,
+        }, depth));
+    }
+}
+
+// flat coloured objects for the coloured pipeline
+pub fn queue_coloured_objects(queue: &mut RenderQueue, states: &[State], frame: &Frame) {
+    for cobj in frame.coloured_objects.iter() {
+        let mvp = frame.view_proj * frame.scene_model * cobj.object.model;
+        let range = frame.meshes.get(cobj.object.mesh);
+        queue.submit(DrawItem::new(states[range.page], range.start, range.count,
+            &ColouredUniforms {
+                mvp,
                 colour: cobj.colour,
-            });
-            ctx.draw(cobj.object.start, cobj.object.end, 1);
-        }
-        ctx.end_render_pass();
+            }, mvp.w_axis.w));
     }
+}
+
+// the same two with one draw per mesh range
+pub fn queue_instanced(queue: &mut RenderQueue, states: &[State],
+    instances: &InstanceBuffer, frame: &Frame) {
+    let batches = Batches::objects(frame.objects, vec4(1., 1., 1., 1.));
+    instances.submit(queue, states, frame.meshes, batches,
+        frame.view_proj * frame.scene_model, &instanced_uniforms(frame));
+}
 
-    pub fn get_output(&self) -> Texture {
-        self.output
+fn instanced_uniforms(frame: &Frame) -> InstancedUniforms {
+    InstancedUniforms {
+        model: frame.scene_model,
+        proj: frame.view_proj,
+        light_pos: frame.light_pos,
+        light_view: frame.light_view,
+        light_proj: frame.light_proj,
     }
 }
 
+pub fn queue_coloured_instanced(queue: &mut RenderQueue, states: &[State],
+    instances: &InstanceBuffer, frame: &Frame) {
+    let batches = Batches::coloured_objects(frame.coloured_objects);
+    let view_proj = frame.view_proj * frame.scene_model;
+    instances.submit(queue, states, frame.meshes, batches, view_proj,
+        &instancing::ColouredUniforms { mvp: view_proj });
+}
+
 const VERTEX: &str = r#"#version 100
 attribute vec4 pos;
 attribute vec3 normal;
@@ -179,6 +233,7 @@ varying vec3 vlight_dir;
 varying vec3 vnormal_view;
 varying vec4 vpos_from_light;
 varying vec4 vshadow_coord;
+varying lowp vec4 vcolour;
 
 uniform mat4 model;
 uniform mat4 proj;
@@ -194,6 +249,37 @@ void main() {
     vshadow_coord = light_proj * vpos_from_light;
     vnormal_view = (normal_matrix * vec4(normal, 0.0)).xyz;
     vlight_dir = (light_pos - position).xyz;
+    vcolour = color0;
+}
+"#;
+
+const INSTANCED_VERTEX: &str = r#"#version 100
+attribute vec4 pos;
+attribute vec3 normal;
+attribute vec4 color0;
+attribute mat4 inst_model;
+attribute mat4 inst_normal_matrix;
+
+varying vec3 vlight_dir;
+varying vec3 vnormal_view;
+varying vec4 vpos_from_light;
+varying vec4 vshadow_coord;
+varying lowp vec4 vcolour;
+
+uniform mat4 model;
+uniform mat4 proj;
+uniform vec4 light_pos;
+uniform mat4 light_proj;
+uniform mat4 light_view;
+
+void main() {
+    vec4 position = model * inst_model * pos;
+    gl_Position = proj * position;
+    vpos_from_light = light_view * position;
+    vshadow_coord = light_proj * vpos_from_light;
+    vnormal_view = (inst_normal_matrix * vec4(normal, 0.0)).xyz;
+    vlight_dir = (light_pos - position).xyz;
+    vcolour = color0;
 }
 "#;
 
@@ -205,6 +291,7 @@ varying vec3 vlight_dir;
 varying vec3 vnormal_view;
 varying vec4 vpos_from_light;
 varying vec4 vshadow_coord;
+varying lowp vec4 vcolour;
 
 uniform sampler2D shadow_map;
 
@@ -238,7 +325,7 @@ void main() {
 
     float lambert = max(0.0, dot(normalize(vlight_dir), normalize(vnormal_view)));
     //float lambert = 1.0;
-    gl_FragColor = vec4(1.0) * clamp(ambient + lambert * shadow, 0.0, 1.0);
+    gl_FragColor = vcolour * clamp(ambient + lambert * shadow, 0.0, 1.0);
 }
 "#;
 
@@ -259,6 +346,7 @@ fn meta() -> ShaderMeta {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Uniforms {
     pub model: glam::Mat4,
     pub proj: glam::Mat4,
@@ -268,6 +356,35 @@ pub struct Uniforms {
     pub light_proj: glam::Mat4,
 }
 
+unsafe impl Pod for Uniforms {}
+
+fn instanced_meta() -> ShaderMeta {
+    ShaderMeta {
+        images: vec!["shadow_map".to_string()],
+        uniforms: UniformBlockLayout {
+            uniforms: vec![
+                UniformDesc::new("model", UniformType::Mat4),
+                UniformDesc::new("proj", UniformType::Mat4),
+                UniformDesc::new("light_pos", UniformType::Float4),
+                UniformDesc::new("light_view", UniformType::Mat4),
+                UniformDesc::new("light_proj", UniformType::Mat4),
+            ]
+        },
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct InstancedUniforms {
+    pub model: glam::Mat4,
+    pub proj: glam::Mat4,
+    pub light_pos: glam::Vec4,
+    pub light_view: glam::Mat4,
+    pub light_proj: glam::Mat4,
+}
+
+unsafe impl Pod for InstancedUniforms {}
+
 const COLOURED_VERTEX: &str = r#"#version 100
 attribute vec4 pos;
 
@@ -302,7 +419,27 @@ fn coloured_meta() -> ShaderMeta {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct ColouredUniforms {
     pub mvp: glam::Mat4,
     pub colour: glam::Vec4,
-}
\ No newline at end of file
+}
+
+unsafe impl Pod for ColouredUniforms {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vertex colours, such as imported base colours, reach the screen
+    #[test]
+    fn lit_shaders_are_tinted_by_vertex_colour() {
+        for vertex in [VERTEX, INSTANCED_VERTEX].iter() {
+            assert!(vertex.contains("attribute vec4 color0;"));
+            assert!(vertex.contains("vcolour = color0;"));
+        }
+        assert!(FRAGMENT.contains("gl_FragColor = vcolour * "));
+        assert_eq!(gpu::check_layout(VERTEX, &[Vertex::layout()], &Vertex::attributes()),
+            Ok(()));
+    }
+}
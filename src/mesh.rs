@@ -0,0 +1,130 @@
+use miniquad::*;
+use glam::{Vec2, Vec3, Vec4};
+
+// geometry in the layout cube_verts uses: position, colour, normal and uv,
+// with u16 indices
+
+// one vertex as the mesh pipelines read it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub pos: [f32; 3],
+    pub colour: [f32; 4],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl Vertex {
+    pub fn new(pos: Vec3, colour: Vec4, normal: Vec3, uv: Vec2) -> Vertex {
+        Vertex {
+            pos: pos.to_array(),
+            colour: colour.to_array(),
+            normal: normal.to_array(),
+            uv: uv.to_array(),
+        }
+    }
+
+    // from 12 floats in field order, as cube_verts has them
+    pub fn from_floats(f: &[f32]) -> Vertex {
+        Vertex {
+            pos: [f[0], f[1], f[2]],
+            colour: [f[3], f[4], f[5], f[6]],
+            normal: [f[7], f[8], f[9]],
+            uv: [f[10], f[11]],
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from(self.pos)
+    }
+
+    // the fields in order, named as the shaders name them. shaders that
+    // don't declare one just skip over it
+    pub fn attributes() -> [VertexAttribute; 4] {
+        [
+            VertexAttribute::new("pos", VertexFormat::Float3),
+            VertexAttribute::new("color0", VertexFormat::Float4),
+            VertexAttribute::new("normal", VertexFormat::Float3),
+            VertexAttribute::new("uv", VertexFormat::Float2),
+        ]
+    }
+
+    pub fn layout() -> BufferLayout {
+        BufferLayout {
+            stride: std::mem::size_of::<Vertex>() as i32,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    // bounds of the positions
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh {
+            vertices: vec![],
+            indices: vec![],
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(f32::MIN),
+        }
+    }
+
+    // from vertices of 12 floats each, as cube_verts has them
+    pub fn from_slices(vertices: &[f32], indices: &[u16]) -> Mesh {
+        let mut mesh = Mesh::new();
+        for v in vertices.chunks(12) {
+            mesh.push(Vertex::from_floats(v)).unwrap();
+        }
+        mesh.indices.extend_from_slice(indices);
+        mesh
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    // widen the bounds to take in p
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    // add a vertex, returning its index, or an error if u16 can't hold it
+    pub fn push(&mut self, v: Vertex) -> Result<u16, String> {
+        let i = self.vertex_count();
+        if i > u16::MAX as usize {
+            return Err(format!("more than {} vertices", u16::MAX as usize + 1));
+        }
+        self.vertices.push(v);
+        self.grow(v.position());
+        Ok(i as u16)
+    }
+
+    // add other's vertices and triangles after these. returns where its
+    // indices start, to offset ranges into it by
+    pub fn append(&mut self, other: &Mesh) -> Result<usize, String> {
+        let base = self.vertex_count();
+        if base + other.vertex_count() > u16::MAX as usize + 1 {
+            return Err(format!("more than {} vertices", u16::MAX as usize + 1));
+        }
+        let start = self.indices.len();
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices.extend(other.indices.iter().map(|i| i + base as u16));
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        Ok(start)
+    }
+}
+
+impl Default for Mesh {
+    fn default() -> Mesh {
+        Mesh::new()
+    }
+}
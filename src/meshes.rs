@@ -0,0 +1,148 @@
+use miniquad::*;
+
+use mq_test::mesh::Mesh;
+use mq_test::primitives;
+use crate::culling::Aabb;
+use crate::objects::{self, Object};
+
+// every mesh that can be drawn, packed one after another into pages of
+// shared vertex and index buffers so one set of bindings serves every mesh
+// in a page. indices are u16, so a page holds up to 65536 vertices and a
+// mesh that won't fit in the last one starts another. objects say which
+// mesh they are by id
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshId(usize);
+
+type Generate = fn() -> Mesh;
+
+// the meshes every registry starts with, in this order, so rules and
+// scenes can name them before there is a registry
+const BUILTIN: &[(&str, Generate)] = &[
+    ("cube", objects::cube_mesh),
+    ("sphere", || primitives::sphere(24, 16).unwrap()),
+    ("cylinder", || primitives::cylinder(24).unwrap()),
+    ("cone", || primitives::cone(24).unwrap()),
+    ("torus", || primitives::torus(32, 12, 0.4).unwrap()),
+    ("plane", || primitives::plane(8).unwrap()),
+];
+
+pub fn builtin(name: &str) -> Option<MeshId> {
+    BUILTIN.iter().position(|(b, _)| *b == name).map(MeshId)
+}
+
+// which page a mesh is in, where its triangles are in that page's index
+// buffer, and the bounds of its vertices
+#[derive(Clone, Copy, Debug)]
+pub struct MeshRange {
+    pub page: usize,
+    pub start: i32,
+    pub count: i32,
+    pub bounds: Aabb,
+}
+
+// the most vertices u16 indices can reach
+const PAGE_VERTICES: usize = u16::MAX as usize + 1;
+
+pub struct MeshRegistry {
+    pages: Vec<Mesh>,
+    ranges: Vec<MeshRange>,
+}
+
+impl MeshRegistry {
+    pub fn new() -> MeshRegistry {
+        let mut registry = MeshRegistry {
+            pages: vec![Mesh::new()],
+            ranges: vec![],
+        };
+        for (_, mesh) in BUILTIN.iter() {
+            registry.add(&mesh()).unwrap();
+        }
+        registry
+    }
+
+    pub fn add(&mut self, mesh: &Mesh) -> Result<MeshId, String> {
+        let last = self.pages.len() - 1;
+        if self.pages[last].vertex_count() + mesh.vertex_count() > PAGE_VERTICES {
+            self.pages.push(Mesh::new());
+        }
+        let page = self.pages.len() - 1;
+        let start = self.pages[page].append(mesh)?;
+        self.ranges.push(MeshRange {
+            page,
+            start: start as i32,
+            count: mesh.indices.len() as i32,
+            bounds: Aabb::new(mesh.min, mesh.max),
+        });
+        Ok(MeshId(self.ranges.len() - 1))
+    }
+
+    pub fn get(&self, id: MeshId) -> &MeshRange {
+        &self.ranges[id.0]
+    }
+
+    // world space bounds of an object
+    pub fn bounds(&self, obj: &Object) -> Aabb {
+        self.get(obj.mesh).bounds.transform(obj.model)
+    }
+
+    // the vertices and indices of every mesh in each page
+    pub fn pages(&self) -> &[Mesh] {
+        &self.pages
+    }
+
+    // the indices of a mesh's triangles, into its page's vertices
+    pub fn indices(&self, id: MeshId) -> &[u16] {
+        let range = self.get(id);
+        &self.pages[range.page].indices
+            [range.start as usize..(range.start + range.count) as usize]
+    }
+
+    // buffers holding every mesh added so far, one set per page
+    pub fn bindings(&self, ctx: &mut Context) -> Vec<Bindings> {
+        self.pages.iter().map(|page| {
+            let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer,
+                &page.vertices);
+            let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer,
+                &page.indices);
+            Bindings {
+                vertex_buffers: vec![vertex_buffer],
+                index_buffer,
+                images: vec![],
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meshes_that_dont_fit_start_a_page() {
+        let mut meshes = MeshRegistry::new();
+        // 40401 vertices, so two won't share a page
+        let big = primitives::plane(200).unwrap();
+        let a = meshes.add(&big).unwrap();
+        let b = meshes.add(&big).unwrap();
+        let c = meshes.add(&objects::cube_mesh()).unwrap();
+        assert_eq!(meshes.pages().len(), 2);
+        assert_eq!(meshes.get(builtin("cube").unwrap()).page, 0);
+        assert_eq!(meshes.get(a).page, 0);
+        assert_eq!((meshes.get(b).page, meshes.get(b).start), (1, 0));
+        assert_eq!(meshes.get(c).page, 1);
+
+        // each mesh's indices still pick out its own vertices
+        for (id, mesh) in [(a, &big), (b, &big), (c, &objects::cube_mesh())].iter() {
+            let page = &meshes.pages()[meshes.get(*id).page];
+            let positions: Vec<[f32; 3]> = meshes.indices(*id).iter()
+                .map(|i| page.vertices[*i as usize].pos)
+                .collect();
+            let expected: Vec<[f32; 3]> = mesh.indices.iter()
+                .map(|i| mesh.vertices[*i as usize].pos)
+                .collect();
+            assert_eq!(positions, expected);
+        }
+        assert!(meshes.pages().iter().all(|p| p.vertex_count() <= PAGE_VERTICES));
+    }
+}
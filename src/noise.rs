@@ -0,0 +1,143 @@
+// 2D coherent noise, the same for a given seed on every platform. value
+// and perlin return roughly -1..1, smoothly varying over about one unit
+
+// splitmix64 finaliser over the seed and lattice point
+fn hash(seed: u64, x: i32, y: i32) -> u64 {
+    let mut h = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+// -1..1 at a lattice point
+fn lattice(seed: u64, x: i32, y: i32) -> f32 {
+    let v = (hash(seed, x, y) >> 40) as f32 / (1u64 << 24) as f32;
+    v * 2.0 - 1.0
+}
+
+// quintic ease, flat at both ends so the noise has no creases
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// random values at integer points, eased between
+pub fn value(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (u, v) = (fade(x - x0), fade(y - y0));
+    lerp(
+        lerp(lattice(seed, ix, iy), lattice(seed, ix + 1, iy), u),
+        lerp(lattice(seed, ix, iy + 1), lattice(seed, ix + 1, iy + 1), u),
+        v,
+    )
+}
+
+// dot of the offset with one of eight gradients picked by the hash
+fn grad(seed: u64, ix: i32, iy: i32, dx: f32, dy: f32) -> f32 {
+    const D: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    let (gx, gy) = match hash(seed, ix, iy) >> 61 {
+        0 => (1.0, 0.0),
+        1 => (-1.0, 0.0),
+        2 => (0.0, 1.0),
+        3 => (0.0, -1.0),
+        4 => (D, D),
+        5 => (-D, D),
+        6 => (D, -D),
+        _ => (-D, -D),
+    };
+    gx * dx + gy * dy
+}
+
+// gradient noise, zero at integer points
+pub fn perlin(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (fx, fy) = (x - x0, y - y0);
+    let (u, v) = (fade(fx), fade(fy));
+    let n = lerp(
+        lerp(grad(seed, ix, iy, fx, fy), grad(seed, ix + 1, iy, fx - 1.0, fy), u),
+        lerp(grad(seed, ix, iy + 1, fx, fy - 1.0),
+            grad(seed, ix + 1, iy + 1, fx - 1.0, fy - 1.0), u),
+        v,
+    );
+    // the most a 2D gradient noise can reach is sqrt(0.5)
+    n * std::f32::consts::SQRT_2
+}
+
+// octaves of noise, each at twice the frequency and half the amplitude of
+// the last, scaled back to the range of noise. no octaves is flat
+pub fn fbm(noise: fn(u64, f32, f32) -> f32, seed: u64, x: f32, y: f32,
+    octaves: u32) -> f32 {
+    if octaves == 0 {
+        return 0.0;
+    }
+    let (mut sum, mut total) = (0.0, 0.0);
+    let (mut freq, mut amp) = (1.0, 1.0);
+    for octave in 0..octaves {
+        sum += amp * noise(seed.wrapping_add(octave as u64), x * freq, y * freq);
+        total += amp;
+        freq *= 2.0;
+        amp *= 0.5;
+    }
+    sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // bit patterns, so any change to the hash or easing shows up here
+    #[test]
+    fn values_are_pinned() {
+        let cases = [(0u64, 0.5f32, 0.5f32), (42, 3.25, -7.75), (7, -120.1, 64.9)];
+        let got: Vec<[u32; 3]> = cases.iter().map(|&(seed, x, y)| [
+            value(seed, x, y).to_bits(),
+            perlin(seed, x, y).to_bits(),
+            fbm(perlin, seed, x, y, 4).to_bits(),
+        ]).collect();
+        assert_eq!(got, vec![
+            [3173937200, 1037308874, 1029846846],
+            [3210699321, 1051923554, 1047480898],
+            [1061667588, 1046136822, 3181546774],
+        ]);
+    }
+
+    #[test]
+    fn perlin_is_zero_at_lattice_points() {
+        for seed in 0..4 {
+            for x in -5..5 {
+                for y in -5..5 {
+                    assert_eq!(perlin(seed, x as f32, y as f32), 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        for seed in 0..4 {
+            for i in -200..200 {
+                for j in -20..20 {
+                    let (x, y) = (i as f32 * 0.37, j as f32 * 0.53);
+                    for n in [value(seed, x, y), perlin(seed, x, y),
+                        fbm(perlin, seed, x, y, 5)].iter() {
+                        assert!((-1.0..=1.0).contains(n), "{} at {} {}", n, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fbm_without_octaves_is_flat() {
+        assert_eq!(fbm(perlin, 3, 1.5, 2.5, 0), 0.0);
+    }
+}
@@ -1,20 +1,30 @@
 use miniquad::*;
 
-use glam::{vec3, vec4, Vec3, Vec4, Mat4, EulerRot};
-use xorshift::{Rng, RngJump, Xoroshiro128, SeedableRng};
+use std::collections::HashMap;
+use glam::{vec3, vec4, ivec2, ivec3, Vec3, Vec4, IVec2, Mat4};
+use crate::culling::Aabb;
+use crate::meshes::{self, MeshId, MeshRegistry};
+use mq_test::noise;
+use mq_test::random::Random;
+use mq_test::mesh::Mesh;
+use crate::terrain::{self, TerrainPatch};
+use crate::rules::{Rules, Layer, Placement};
+use crate::entities::{Entities, Transform, Velocity, AngularVelocity, Hover,
+    ColourAnimation, Wrap};
 
+#[derive(Clone, Copy)]
 pub struct Object {
     pub model:Mat4,
-    pub start:i32,
-    pub end:i32
+    pub mesh:MeshId
 }
 
+#[derive(Clone, Copy)]
 pub struct ColouredObject {
     pub object:Object,
     pub colour:Vec4
 }
 
-fn cube_verts() -> (&'static[f32], &'static[u16]) {
+pub fn cube_verts() -> (&'static[f32], &'static[u16]) {
     #[rustfmt::skip]
     let vertices: &[f32] = &[
         /* pos               color                   normal         uvs */
@@ -62,126 +72,365 @@ fn cube_verts() -> (&'static[f32], &'static[u16]) {
     (vertices, indices)
 }
 
-pub fn cube_bindings(ctx: &mut Context) -> Bindings {
+// the lit pipes tint by vertex colour, and the cubes have always been
+// drawn white, so cube_verts' colours are left out
+pub fn cube_mesh() -> Mesh {
     let (vertices, indices) = cube_verts();
-    let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices);
-    let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &indices);
-
-    Bindings {
-        vertex_buffers: vec![vertex_buffer],
-        index_buffer: index_buffer,
-        images: vec![],
-    }
-}
-
-fn rng_from_pos(pos:Vec3, i:u64) -> Xoroshiro128 {
-    let h = i32::MAX as i64;
-    let mut rng: Xoroshiro128 = SeedableRng::from_seed(&[1, i][..]);
-    // first output is just the seed so jump to somewhere interesting
-    rng.jump(1);
-    for d in pos.to_array().iter() {
-        let v = ((*d as i32) as i64 + h) as u64;
-        let seed = [rng.next_u64(), rng.next_u64() + v];
-        rng.reseed(&seed[..]);
-        rng.jump(1);
-    }
-    rng
-}
-
-fn cube(pos: Vec3) -> Mat4 {
-    // a cuboid with random scale, rotation at pos
-    let mut rng = rng_from_pos(pos, 1);
-    let rot = Mat4::from_euler(EulerRot::YXZ, 
-        rng.gen_range(-std::f32::consts::PI, std::f32::consts::PI),
-        0.0, 0.);
-    let s = 0.125 * rng.gen_range(0.5, 1.1);
-    let y = 10.0 * s;
+    let mut mesh = Mesh::from_slices(vertices, indices);
+    for v in mesh.vertices.iter_mut() {
+        v.colour = [1.0, 1.0, 1.0, 1.0];
+    }
+    mesh
+}
+
+// the world seed the demo has always used
+pub const DEFAULT_SEED: u64 = 1;
+
+// a stream for stream i of the whole unit cell at pos
+fn rng_from_pos(seed:u64, pos:Vec3, i:u64) -> Random {
+    let cell = |d: f32| d as i32 as u32 as u64;
+    Random::new(seed, &[i, cell(pos.x), cell(pos.y), cell(pos.z)])
+}
+
+// a built in mesh by name, as an object with no transform
+pub fn mesh(name: &str) -> Option<Object> {
+    meshes::builtin(name).map(|mesh| Object {
+        model: Mat4::IDENTITY,
+        mesh,
+    })
+}
+
+fn range(rng: &mut Random, r: [f32; 2]) -> f32 {
+    rng.range(r[0], r[1])
+}
+
+fn colour(rng: &mut Random, layer: &Layer, i: usize) -> Vec4 {
+    match layer.placement {
+        Placement::Grid if !layer.palette.is_empty() =>
+            Vec4::from(layer.palette[rng.below(layer.palette.len() as u32) as usize]),
+        _ => layer.palette.get(i % layer.palette.len().max(1))
+            .map_or(vec4(1., 1., 1., 1.), |c| Vec4::from(*c)),
+    }
+}
+
+// sin and cos of an angle in degrees from + and * alone. std's come from
+// the platform's libm and can differ in the last bit between targets,
+// which would move objects between the native and wasm builds
+fn sin_cos_degrees(degrees: f32) -> (f32, f32) {
+    // down to within 45 degrees of a quarter turn
+    let quarter = (degrees / 90.0).round();
+    let x = (degrees - quarter * 90.0) * (std::f32::consts::PI / 180.0);
+    let x2 = x * x;
+    // taylor series, well inside an f32's precision this near 0
+    let s = x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0
+        * (1.0 - x2 / 72.0))));
+    let c = 1.0 - x2 / 2.0 * (1.0 - x2 / 12.0 * (1.0 - x2 / 30.0
+        * (1.0 - x2 / 56.0 * (1.0 - x2 / 90.0))));
+    match (quarter as i64).rem_euclid(4) {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    }
+}
+
+// an object of the layer at a random scale and rotation, stood on the
+// ground at pos. tall is a multiple of the layer's height
+fn place(rng: &mut Random, layer: &Layer, pos: Vec3, tall: f32) -> Object {
+    // a turn about y, as Mat4::from_rotation_y but without libm
+    let (sin, cos) = sin_cos_degrees(range(rng, layer.rotation));
+    let rot = Mat4::from_cols(
+        vec4(cos, 0., -sin, 0.),
+        vec4(0., 1., 0., 0.),
+        vec4(sin, 0., cos, 0.),
+        vec4(0., 0., 0., 1.),
+    );
+    let s = range(rng, layer.scale);
+    let y = layer.height * s * tall;
     let scale = Mat4::from_scale(vec3(s, y, s));
+    // sunk a little so slopes don't show under it
     let trans = Mat4::from_translation(vec3(
         pos.x,
-        pos.y + y - 1.0,
+        pos.y + y - 0.05,
         pos.z,
     ));
-    trans * scale * rot
+    Object {
+        model: trans * scale * rot,
+        ..mesh(&layer.mesh).unwrap()
+    }
 }
 
-pub fn cubes(pos: Vec3) -> Vec<Object> {
-    // a grid of cuboids on integer coords surrounding pos
-    // plus a ground plane centred at pos
-    let o = vec3(-pos.x.floor(), -pos.y.floor(), -pos.z.floor());
-    let mut cubes = Vec::<Mat4>::new();
-    for z in -3..6 {
-        for x in -3..3 {
-            cubes.push(cube(o + vec3(x as f32, 0., z as f32)));
-        }
+// noise frequencies for placing grid layers, in cycles per cell
+const DISTRICT_SCALE: f32 = 0.04;
+const CLEARING_SCALE: f32 = 0.15;
+// value noise below this leaves the cell empty
+const CLEARING_LEVEL: f32 = -0.35;
+
+fn grid_object(seed: u64, layer: &Layer, index: usize, pos: Vec3) -> Option<ColouredObject> {
+    // cells fall into clearings and into districts of tall or short
+    // objects by noise
+    let clearing = noise::value(seed.wrapping_add(16),
+        pos.x * CLEARING_SCALE, pos.z * CLEARING_SCALE);
+    if clearing < CLEARING_LEVEL {
+        return None;
+    }
+    let district = noise::fbm(noise::perlin, seed,
+        pos.x * DISTRICT_SCALE, pos.z * DISTRICT_SCALE, 4);
+
+    let mut rng = rng_from_pos(seed, pos, index as u64 + 1);
+    let ground = vec3(pos.x, terrain::height(seed, pos.x, pos.z), pos.z);
+    // a quarter to twice the layer's height
+    let object = place(&mut rng, layer, ground, 1.125 + 0.875 * district);
+    let colour = colour(&mut rng, layer, 0);
+    if range(&mut rng, [0.0, 1.0]) >= layer.density {
+        return None;
     }
-    let trans = Mat4::from_translation(vec3(0., 0., 2.));
-    let rot = Mat4::from_euler(EulerRot::YXZ, 
-        0., std::f32::consts::PI / 2., 0.);
-    let scale = Mat4::from_scale(vec3(10.0, 1.0, 10.0));
-    let trans2 = Mat4::from_translation(-pos);
-    let ground_plane = trans2 * scale * rot * trans;
+    Some(ColouredObject { object, colour })
+}
+
+// cells along each side of a chunk
+pub const CHUNK_SIZE: i32 = 8;
 
+// a square of CHUNK_SIZE by CHUNK_SIZE cells with the grid layers' objects
+// in them, on a patch of terrain
+pub struct Chunk {
+    pub objects: Vec<Object>,
+    pub coloured_objects: Vec<ColouredObject>,
+    pub terrain: TerrainPatch,
+    // world space bounds of the terrain and every object in the chunk
+    pub bounds: Aabb,
+}
+
+// the grid layers' objects in the chunk at coord, plain and glowing
+fn grid_objects(seed: u64, rules: &Rules, coord: IVec2) -> (Vec<Object>, Vec<ColouredObject>) {
+    let origin = coord * CHUNK_SIZE;
     let mut objects = Vec::<Object>::new();
-    for cube in cubes.iter() {
-        objects.push(Object {
-            model: *cube,
-            start: 0,
-            end: 36
+    let mut coloured_objects = Vec::<ColouredObject>::new();
+    for (index, layer) in rules.layers.iter().enumerate() {
+        if layer.placement != Placement::Grid {
+            continue;
+        }
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let cell = ivec3(origin.x + x, 0, origin.y + z);
+                match grid_object(seed, layer, index, cell.as_vec3()) {
+                    Some(cobj) if layer.glow => coloured_objects.push(cobj),
+                    Some(cobj) => objects.push(cobj.object),
+                    None => {}
+                }
+            }
+        }
+    }
+    (objects, coloured_objects)
+}
+
+impl Chunk {
+    fn generate(ctx: &mut Context, seed: u64, rules: &Rules, meshes: &MeshRegistry,
+        coord: IVec2) -> Chunk {
+        let origin = coord * CHUNK_SIZE;
+        let (objects, coloured_objects) = grid_objects(seed, rules, coord);
+        let terrain = TerrainPatch::new(ctx, seed,
+            vec3(origin.x as f32, 0., origin.y as f32), CHUNK_SIZE as f32);
+        let mut bounds = terrain.bounds;
+        let all = objects.iter().chain(coloured_objects.iter().map(|c| &c.object));
+        for obj in all {
+            let b = meshes.bounds(obj);
+            bounds = Aabb::new(bounds.min.min(b.min), bounds.max.max(b.max));
+        }
+        Chunk { objects, coloured_objects, terrain, bounds }
+    }
+}
+
+fn chunk_coord(pos: Vec3) -> IVec2 {
+    let size = CHUNK_SIZE as f32;
+    ivec2((pos.x / size).floor() as i32, (pos.z / size).floor() as i32)
+}
+
+// the chunks within load_radius of a point, generated as they come into
+// range. a chunk is only dropped once it is further than load_radius plus
+// hysteresis, so moving back and forth over a border doesn't regenerate it
+pub struct ChunkManager {
+    seed: u64,
+    rules: Rules,
+    chunks: HashMap<IVec2, Chunk>,
+    centre: Option<IVec2>,
+}
+
+impl ChunkManager {
+    pub fn new(seed: u64, rules: &Rules) -> ChunkManager {
+        ChunkManager {
+            seed,
+            rules: rules.clone(),
+            chunks: HashMap::new(),
+            centre: None,
+        }
+    }
+
+    // load and unload around the world position pos
+    pub fn update(&mut self, ctx: &mut Context, meshes: &MeshRegistry, pos: Vec3) {
+        let centre = chunk_coord(pos);
+        if self.centre == Some(centre) {
+            return;
+        }
+        self.centre = Some(centre);
+
+        let keep = self.rules.load_radius + self.rules.hysteresis;
+        self.chunks.retain(|coord, _| {
+            let d = (*coord - centre).abs();
+            d.x.max(d.y) <= keep
         });
+        let (seed, rules) = (self.seed, &self.rules);
+        let r = rules.load_radius;
+        for z in -r..=r {
+            for x in -r..=r {
+                let coord = centre + ivec2(x, z);
+                self.chunks.entry(coord)
+                    .or_insert_with(|| Chunk::generate(ctx, seed, rules, meshes, coord));
+            }
+        }
     }
-    objects.push(Object{
-        model: ground_plane,
-        start: 0,
-        end: 6
-    });
 
-    objects
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
 }
 
-fn wrap(a:Vec3, min:Vec3, max:Vec3) -> Vec3 {
+pub fn wrap(a:Vec3, min:Vec3, max:Vec3) -> Vec3 {
     let o = a - min;
     let s = max - min;
     let r = (o / s).fract();
     (r * s) + min
 }
 
-pub fn coloured_cubes(pos:Vec3) -> Vec<ColouredObject> {
-    // coloured cubes at random scale, rotation, and position wrapped to be
-    // within fixed bounds of pos
-    let colours = vec![
-        vec4(1., 0., 0., 1.),
-        vec4(1., 0.5, 0., 1.),
-        vec4(1., 1., 0., 1.),
-        vec4(0., 1., 0., 1.),
-        vec4(0., 0., 1., 1.),
-        vec4(0.3, 0., 0.5, 1.),
-        vec4(0.5, 0., 0.5, 1.),
-    ];
-    let mut rng = rng_from_pos(vec3(0., 0., 0., ), 2);
-
-    let mut cubes = Vec::<ColouredObject>::new();
-    for colour in colours {
-        let x:f32 = rng.gen_range(-10.0, 10.0);
-        let z:f32 = rng.gen_range(-10.0, 10.0);
-        let rot = Mat4::from_euler(EulerRot::YXZ, 
-            rng.gen_range(-std::f32::consts::PI, std::f32::consts::PI),
-            0.0, 0.);
-        let s = rng.gen_range(0.4, 0.6);
-        let scale = Mat4::from_scale(vec3(s, s, s));
-        let p = vec3(x, s - 1.0, z);
-        let p = wrap(p, -pos - vec3(10.0, 10.0, 10.0),
-                        -pos + vec3(10.0, 10.0, 10.0));
-        let trans = Mat4::from_translation(p);
-        cubes.push(
-            ColouredObject {
-                object: Object {
-                    model: trans * scale * rot,
-                    start: 0,
-                    end: 36
-                },
-                colour
+// entities for the scatter layers at random scale, rotation, and position
+// within spread of -pos, where the camera is, set moving by the layer's
+// drift, spin, bob and pulse
+pub fn spawn_scatter(seed:u64, rules:&Rules, pos:Vec3, entities:&mut Entities) {
+    for (index, layer) in rules.layers.iter().enumerate() {
+        if layer.placement != Placement::Scatter {
+            continue;
+        }
+        let mut rng = rng_from_pos(seed, vec3(0., 0., 0., ), index as u64 + 1);
+        let spread = Vec3::splat(layer.spread);
+        for i in 0..layer.count {
+            let x = range(&mut rng, [-layer.spread, layer.spread]);
+            let z = range(&mut rng, [-layer.spread, layer.spread]);
+            let mut p = wrap(vec3(x, 0., z), -pos - spread, -pos + spread);
+            p.y = terrain::height(seed, p.x, p.z);
+            let placed = place(&mut rng, layer, p, 1.0);
+            let (scale, rotation, position) = placed.model.to_scale_rotation_translation();
+            let e = entities.spawn(placed, Transform { position, rotation, scale });
+            entities.set_wrap(e, Wrap { spread: layer.spread });
+            entities.set_hover(e, Hover {
+                height: scale.y - 0.05 + layer.bob,
+                amplitude: layer.bob,
+                rate: range(&mut rng, [0.2, 0.5]),
+                phase: range(&mut rng, [0.0, 1.0]),
             });
+
+            let (sin, cos) = sin_cos_degrees(range(&mut rng, [-180.0, 180.0]));
+            let speed = range(&mut rng, [0.0, layer.drift]);
+            entities.set_velocity(e, Velocity(vec3(cos, 0., sin) * speed));
+            let axis = vec3(range(&mut rng, [-1.0, 1.0]), 1.0,
+                range(&mut rng, [-1.0, 1.0])).normalize();
+            let spin = range(&mut rng, [-layer.spin, layer.spin]).to_radians();
+            entities.set_angular_velocity(e, AngularVelocity(axis * spin));
+
+            if layer.glow {
+                entities.set_colour(e, ColourAnimation {
+                    colour: colour(&mut rng, layer, i),
+                    pulse: layer.pulse,
+                    rate: range(&mut rng, [0.2, 0.5]),
+                    phase: range(&mut rng, [0.0, 1.0]),
+                });
+            }
+        }
     }
-    cubes
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        load_radius = 1
+        hysteresis = 1
+
+        [[layer]]
+        mesh = "cube"
+        placement = "grid"
+        density = 0.8
+        scale = [0.0625, 0.1375]
+        height = 10.0
+
+        [[layer]]
+        mesh = "sphere"
+        placement = "grid"
+        density = 0.2
+        scale = [0.1, 0.2]
+        glow = true
+        palette = [[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]]
+    "#;
+
+    // fnv-1a over the bits of every matrix and colour, in order
+    fn fingerprint(objects: &[Object], coloured: &[ColouredObject]) -> u64 {
+        let models = objects.iter().chain(coloured.iter().map(|c| &c.object))
+            .flat_map(|o| o.model.to_cols_array().to_vec());
+        let colours = coloured.iter().flat_map(|c| c.colour.to_array().to_vec());
+        models.chain(colours).fold(0xcbf2_9ce4_8422_2325, |h, f| {
+            (h ^ f.to_bits() as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    fn bits(m: Mat4) -> [u32; 16] {
+        let mut out = [0; 16];
+        for (b, f) in out.iter_mut().zip(m.to_cols_array().iter()) {
+            *b = f.to_bits();
+        }
+        out
+    }
+
+    // computed once and stored, so a change in any platform's float or
+    // integer behaviour, or to the placement code, shows up here
+    #[test]
+    fn grid_objects_are_pinned() {
+        let rules = Rules::parse(RULES).unwrap();
+        let (objects, coloured) = grid_objects(42, &rules, ivec2(3, -2));
+        assert_eq!((objects.len(), coloured.len()), (46, 10));
+        assert_eq!(bits(objects[0].model), [
+            0x3d697386, 0x00000000, 0x3decb0c7, 0x00000000,
+            0x00000000, 0x3fc4de86, 0x00000000, 0x00000000,
+            0xbdecb0c7, 0x00000000, 0x3d697386, 0x00000000,
+            0x41c00000, 0x3f1ab7e6, 0xc1800000, 0x3f800000,
+        ]);
+        assert_eq!(bits(coloured[0].object.model), [
+            0x3e2a626f, 0x00000000, 0xbd7c28e8, 0x00000000,
+            0x00000000, 0x3e4ff918, 0x00000000, 0x00000000,
+            0x3d7c28e8, 0x00000000, 0x3e2a626f, 0x00000000,
+            0x41d00000, 0xbf52c125, 0xc1800000, 0x3f800000,
+        ]);
+        assert_eq!(fingerprint(&objects, &coloured), 0xfcabe58e130c3443);
+
+        let (objects, coloured) = grid_objects(7, &rules, ivec2(0, 0));
+        assert_eq!((objects.len(), coloured.len()), (52, 9));
+        assert_eq!(fingerprint(&objects, &coloured), 0xcb99a873b903bfa8);
+    }
+
+    #[test]
+    fn sin_cos_degrees_matches_std() {
+        for &(d, s, c) in [(0.0, 0.0, 1.0), (90.0, 1.0, 0.0), (-90.0, -1.0, 0.0),
+            (180.0, 0.0, -1.0), (-180.0, 0.0, -1.0)].iter() {
+            assert_eq!(sin_cos_degrees(d), (s, c));
+        }
+        for i in -3600..=3600 {
+            let d = i as f32 * 0.1;
+            let (s, c) = sin_cos_degrees(d);
+            let (es, ec) = d.to_radians().sin_cos();
+            assert!((s - es).abs() < 1e-6 && (c - ec).abs() < 1e-6, "{} degrees", d);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use miniquad::*;
+use mq_test::backend::{Backend, Pod};
+use mq_test::gpu::{self, OwnedPipeline};
+use mq_test::quad_verts;
+use crate::render_graph::{Frame, RenderNode};
+use crate::target::{Size, Target};
+
+pub fn quad_bindings(ctx: &mut Context) -> Bindings {
+    let (vertices, indices) = quad_verts();
+    let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, vertices);
+    let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, indices);
+
+    Bindings {
+        vertex_buffers: vec![vertex_buffer],
+        index_buffer,
+        images: vec![],
+    }
+}
+
+// where a post-process node draws to
+pub enum Output {
+    Screen,
+    Texture {
+        name: &'static str,
+        size: Size,
+        format: TextureFormat,
+    },
+}
+
+// a fullscreen quad pass: samples its input textures with the given
+// fragment shader and draws to the screen or to its own render target
+pub struct PostNode<U> {
+    target: Option<Target>,
+    pipe: OwnedPipeline,
+    bind: Bindings,
+    uniforms: U,
+    inputs: Vec<&'static str>,
+    outputs: Vec<&'static str>,
+}
+
+impl<U> PostNode<U> {
+    // meta lists one sampler per input, in the same order, and the
+    // uniform block matching U
+    pub fn new(ctx: &mut Context,
+        quad: &Bindings,
+        fragment_shader: &str,
+        meta: ShaderMeta,
+        inputs: Vec<&'static str>,
+        output: Output,
+        uniforms: U) -> PostNode<U> {
+        assert_eq!(inputs.len(), meta.images.len(),
+            "post node needs one sampler per input");
+
+        let (target, outputs) = match output {
+            Output::Screen => (None, vec![]),
+            Output::Texture { name, size, format } => {
+                let target = Target::new(ctx, name, size, format, false);
+                (Some(target), vec![name])
+            }
+        };
+
+        let shader = Shader::new(
+            ctx,
+            VERTEX,
+            fragment_shader,
+            meta,
+        )
+        .unwrap();
+
+        let pipe = gpu::checked_pipeline(
+            ctx,
+            VERTEX,
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("pos", VertexFormat::Float2),
+                VertexAttribute::new("uv", VertexFormat::Float2),
+            ],
+            shader,
+            Default::default(),
+        )
+        .unwrap();
+
+        let label = outputs.first().copied().unwrap_or("screen");
+        PostNode {
+            target,
+            pipe: OwnedPipeline::new(pipe, label),
+            bind: quad.clone(),
+            uniforms,
+            inputs,
+            outputs,
+        }
+    }
+
+    pub fn set_uniforms(&mut self, uniforms: U) {
+        self.uniforms = uniforms;
+    }
+
+    // size of the target drawn to, None when drawing to the screen
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.target.as_ref().map(|t| t.size())
+    }
+}
+
+impl<U: Pod> RenderNode for PostNode<U> {
+    fn inputs(&self) -> &[&'static str] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[&'static str] {
+        &self.outputs
+    }
+
+    fn set_input(&mut self, slot: usize, tex: Texture) {
+        if self.bind.images.len() <= slot {
+            self.bind.images.resize(slot + 1, tex);
+        }
+        self.bind.images[slot] = tex;
+    }
+
+    fn get_output(&self, _slot: usize) -> Option<Texture> {
+        self.target.as_ref().map(|t| t.output())
+    }
+
+    fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if let Some(target) = self.target.as_mut() {
+            target.resize(ctx, width, height);
+        }
+    }
+
+    fn draw(&self, gfx: &mut dyn Backend, _frame: &Frame) {
+        match &self.target {
+            Some(target) => gfx.begin_pass(
+                Some(target.pass()),
+                PassAction::clear_color(0.0, 0.0, 0.0, 1.0),
+            ),
+            None => gfx.begin_default_pass(PassAction::Nothing),
+        }
+        gfx.apply_pipeline(&self.pipe);
+        gfx.apply_bindings(&self.bind);
+        // shaders without uniforms use U = ()
+        if std::mem::size_of::<U>() > 0 {
+            gfx.apply_uniforms(&self.uniforms);
+        }
+        gfx.draw(0, 6, 1);
+        gfx.end_render_pass();
+    }
+}
+
+const VERTEX: &str = r#"#version 100
+attribute vec2 pos;
+attribute vec2 uv;
+
+varying lowp vec2 texcoord;
+
+void main() {
+    gl_Position = vec4(pos, 0, 1);
+    texcoord = uv;
+}
+"#;
@@ -0,0 +1,277 @@
+use std::f32::consts::{PI, TAU};
+use glam::{vec2, vec3, vec4, Vec2, Vec3};
+use crate::mesh::{Mesh, Vertex};
+
+// meshes made from a few numbers, in the layout cube_verts uses. each fits
+// the same -1 to 1 box as the cube so a layer can swap one for another.
+// triangles wind anticlockwise seen from outside, and the seams repeat
+// their vertices so uvs wrap cleanly
+
+// a white vertex, returning its index
+fn vertex(mesh: &mut Mesh, p: Vec3, n: Vec3, uv: Vec2) -> Result<u16, String> {
+    mesh.push(Vertex::new(p, vec4(1.0, 1.0, 1.0, 1.0), n, uv))
+}
+
+// a point of an outline to sweep around the y axis: its radius and height,
+// the outward normal's radial and vertical parts, and v
+#[derive(Clone, Copy)]
+struct Outline {
+    r: f32,
+    y: f32,
+    nr: f32,
+    ny: f32,
+    v: f32,
+}
+
+// each strip of outline points swept around the y axis in segments steps.
+// strips run up the surface, so their quads face out. where a point is on
+// the axis the triangles that would collapse to a line are left out
+fn revolve(segments: u16, strips: &[Vec<Outline>]) -> Result<Mesh, String> {
+    if segments < 3 {
+        return Err("needs at least 3 segments".to_string());
+    }
+    let mut mesh = Mesh::new();
+    let row = segments as usize + 1;
+    for strip in strips.iter() {
+        let base = mesh.vertex_count();
+        for p in strip.iter() {
+            for i in 0..row {
+                let u = i as f32 / segments as f32;
+                let (s, c) = (u * TAU).sin_cos();
+                vertex(&mut mesh, vec3(p.r * c, p.y, -p.r * s),
+                    vec3(p.nr * c, p.ny, -p.nr * s), vec2(u, p.v))?;
+            }
+        }
+        for k in 0..strip.len() - 1 {
+            for i in 0..segments as usize {
+                let a = (base + k * row + i) as u16;
+                let (b, c, d) = (a + 1, a + row as u16 + 1, a + row as u16);
+                if strip[k].r > f32::EPSILON {
+                    mesh.indices.extend_from_slice(&[a, b, c]);
+                }
+                if strip[k + 1].r > f32::EPSILON {
+                    mesh.indices.extend_from_slice(&[a, c, d]);
+                }
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+// radius 1, with segments around and rings from pole to pole
+pub fn sphere(segments: u16, rings: u16) -> Result<Mesh, String> {
+    if rings < 2 {
+        return Err("a sphere needs at least 2 rings".to_string());
+    }
+    let outline = (0..=rings).map(|k| {
+        let v = k as f32 / rings as f32;
+        let (r, y) = (v * PI).sin_cos();
+        Outline { r, y: -y, nr: r, ny: -y, v }
+    }).collect();
+    revolve(segments, &[outline])
+}
+
+// radius 1 and height 2, capped at both ends
+pub fn cylinder(segments: u16) -> Result<Mesh, String> {
+    let at = |r, y, nr, ny, v| Outline { r, y, nr, ny, v };
+    revolve(segments, &[
+        vec![at(0.0, -1.0, 0.0, -1.0, 0.0), at(1.0, -1.0, 0.0, -1.0, 0.25)],
+        vec![at(1.0, -1.0, 1.0, 0.0, 0.25), at(1.0, 1.0, 1.0, 0.0, 0.75)],
+        vec![at(1.0, 1.0, 0.0, 1.0, 0.75), at(0.0, 1.0, 0.0, 1.0, 1.0)],
+    ])
+}
+
+// a base of radius 1 at y -1 up to a point at y 1
+pub fn cone(segments: u16) -> Result<Mesh, String> {
+    // the side rises 2 for every 1 it comes in
+    let side = vec2(2.0, 1.0).normalize();
+    let at = |r, y, nr, ny, v| Outline { r, y, nr, ny, v };
+    revolve(segments, &[
+        vec![at(0.0, -1.0, 0.0, -1.0, 0.0), at(1.0, -1.0, 0.0, -1.0, 0.5)],
+        vec![at(1.0, -1.0, side.x, side.y, 0.5), at(0.0, 1.0, side.x, side.y, 1.0)],
+    ])
+}
+
+// a ring reaching out to radius 1, with a tube thickness across. segments
+// go around the ring and sides around the tube
+pub fn torus(segments: u16, sides: u16, thickness: f32) -> Result<Mesh, String> {
+    if sides < 3 {
+        return Err("a torus needs at least 3 sides".to_string());
+    }
+    if thickness <= 0.0 || thickness > 1.0 {
+        return Err("torus thickness must be over 0 and at most 1".to_string());
+    }
+    let tube = thickness * 0.5;
+    let outline = (0..=sides).map(|k| {
+        let v = k as f32 / sides as f32;
+        let (s, c) = (v * TAU).sin_cos();
+        Outline { r: 1.0 - tube + tube * c, y: tube * s, nr: c, ny: s, v }
+    }).collect();
+    revolve(segments, &[outline])
+}
+
+// a square from -1 to 1 in x and z facing up, split into divisions by
+// divisions squares
+pub fn plane(divisions: u16) -> Result<Mesh, String> {
+    if divisions < 1 {
+        return Err("a plane needs at least 1 division".to_string());
+    }
+    let mut mesh = Mesh::new();
+    let n = divisions as usize;
+    for j in 0..=n {
+        for i in 0..=n {
+            let uv = vec2(i as f32, j as f32) / n as f32;
+            let p = vec3(uv.x * 2.0 - 1.0, 0.0, uv.y * 2.0 - 1.0);
+            vertex(&mut mesh, p, Vec3::Y, uv)?;
+        }
+    }
+    for j in 0..n {
+        for i in 0..n {
+            let a = (j * (n + 1) + i) as u16;
+            let (b, c, d) = (a + 1, a + n as u16 + 2, a + n as u16 + 1);
+            mesh.indices.extend_from_slice(&[a, c, b, a, d, c]);
+        }
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    type Key = [i32; 3];
+
+    // positions rounded so the repeated seam vertices meet
+    fn key(p: Vec3) -> Key {
+        let q = (p * 1e4).round();
+        [q.x as i32, q.y as i32, q.z as i32]
+    }
+
+    fn triangles(mesh: &Mesh) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        mesh.indices.chunks_exact(3).map(move |t| {
+            let p = |i: u16| mesh.vertices[i as usize].position();
+            [p(t[0]), p(t[1]), p(t[2])]
+        })
+    }
+
+    // every edge one way round a triangle is the other way round another,
+    // as many times over, so the surface has no holes or flipped faces
+    fn assert_closed(name: &str, mesh: &Mesh) {
+        let mut edges = HashMap::<(Key, Key), i32>::new();
+        for t in triangles(mesh) {
+            for i in 0..3 {
+                let (a, b) = (key(t[i]), key(t[(i + 1) % 3]));
+                *edges.entry((a, b)).or_insert(0) += 1;
+                *edges.entry((b, a)).or_insert(0) -= 1;
+            }
+        }
+        for (edge, count) in edges.iter() {
+            assert_eq!(*count, 0, "{}: edge {:?} is open", name, edge);
+        }
+    }
+
+    // each triangle winds anticlockwise seen from the side its vertex
+    // normals face, and that side is away from centre
+    fn assert_outward(name: &str, mesh: &Mesh, centre: impl Fn(Vec3) -> Vec3) {
+        for (t, corners) in triangles(mesh).zip(mesh.indices.chunks_exact(3)) {
+            let wound = (t[1] - t[0]).cross(t[2] - t[0]);
+            assert!(wound.length() > 0.0, "{}: degenerate triangle {:?}", name, t);
+            for i in corners.iter() {
+                let n = Vec3::from(mesh.vertices[*i as usize].normal);
+                assert!(wound.dot(n) > 0.0, "{}: {:?} winds against its normals", name, t);
+            }
+            let mid = (t[0] + t[1] + t[2]) / 3.0;
+            assert!(wound.dot(mid - centre(mid)) > 0.0, "{}: {:?} faces in", name, t);
+        }
+    }
+
+    fn assert_bounds(name: &str, mesh: &Mesh, extent: Vec3) {
+        assert!((mesh.min + extent).abs().max_element() < 1e-5,
+            "{}: min {:?}", name, mesh.min);
+        assert!((mesh.max - extent).abs().max_element() < 1e-5,
+            "{}: max {:?}", name, mesh.max);
+    }
+
+    #[test]
+    fn spheres_are_closed_and_face_out() {
+        for &(segments, rings) in [(3, 2), (8, 4), (16, 9), (32, 16)].iter() {
+            let mesh = sphere(segments, rings).unwrap();
+            assert_closed("sphere", &mesh);
+            assert_outward("sphere", &mesh, |_| Vec3::ZERO);
+            if segments % 4 == 0 && rings % 2 == 0 {
+                assert_bounds("sphere", &mesh, Vec3::ONE);
+            }
+        }
+    }
+
+    #[test]
+    fn cylinders_are_closed_and_face_out() {
+        for &segments in [3, 8, 16, 32].iter() {
+            let mesh = cylinder(segments).unwrap();
+            assert_closed("cylinder", &mesh);
+            assert_outward("cylinder", &mesh, |_| Vec3::ZERO);
+            if segments % 4 == 0 {
+                assert_bounds("cylinder", &mesh, Vec3::ONE);
+            }
+        }
+    }
+
+    #[test]
+    fn cones_are_closed_and_face_out() {
+        for &segments in [3, 8, 16, 32].iter() {
+            let mesh = cone(segments).unwrap();
+            assert_closed("cone", &mesh);
+            assert_outward("cone", &mesh, |_| Vec3::ZERO);
+            if segments % 4 == 0 {
+                assert_bounds("cone", &mesh, Vec3::ONE);
+            }
+        }
+    }
+
+    #[test]
+    fn tori_are_closed_and_face_out() {
+        for &(segments, sides, thickness) in [(3, 3, 1.0), (16, 8, 0.5), (32, 12, 0.25)].iter() {
+            let mesh = torus(segments, sides, thickness).unwrap();
+            let tube = thickness * 0.5;
+            assert_closed("torus", &mesh);
+            // away from the middle of the tube
+            assert_outward("torus", &mesh, |p| {
+                vec3(p.x, 0.0, p.z).normalize() * (1.0 - tube)
+            });
+            if segments % 4 == 0 && sides % 4 == 0 {
+                assert_bounds("torus", &mesh, vec3(1.0, tube, 1.0));
+            }
+        }
+    }
+
+    // the plane has an edge, so it's only checked for facing up
+    #[test]
+    fn planes_face_up() {
+        for &divisions in [1, 2, 7].iter() {
+            let mesh = plane(divisions).unwrap();
+            assert_eq!(mesh.indices.len(), divisions as usize * divisions as usize * 6);
+            assert_outward("plane", &mesh, |p| p - Vec3::Y);
+            assert_bounds("plane", &mesh, vec3(1.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn too_few_steps_are_errors() {
+        assert!(sphere(2, 4).is_err());
+        assert!(sphere(8, 1).is_err());
+        assert!(cylinder(2).is_err());
+        assert!(cone(2).is_err());
+        assert!(torus(2, 8, 0.5).is_err());
+        assert!(torus(8, 2, 0.5).is_err());
+        assert!(plane(0).is_err());
+    }
+
+    #[test]
+    fn torus_thickness_is_checked() {
+        assert!(torus(8, 8, 0.0).is_err());
+        assert!(torus(8, 8, -0.5).is_err());
+        assert!(torus(8, 8, 1.5).is_err());
+        assert!(torus(8, 8, 1.0).is_ok());
+    }
+}
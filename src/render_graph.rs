@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use mq_test::render_queue::QueueStats;
+use miniquad::*;
+use mq_test::backend::Backend;
+use glam::{Vec4, Mat4};
+use crate::objects::{Object, ColouredObject};
+use crate::terrain::TerrainPatch;
+use crate::meshes::MeshRegistry;
+
+// everything a pass may need to know about the frame being drawn
+pub struct Frame<'a> {
+    // what the objects' mesh ids refer to
+    pub meshes: &'a MeshRegistry,
+    // what the camera can see
+    pub objects: &'a [Object],
+    pub coloured_objects: &'a [ColouredObject],
+    pub terrain: &'a [&'a TerrainPatch],
+    // what the light can see
+    pub shadow_casters: &'a [Object],
+    pub shadow_terrain: &'a [&'a TerrainPatch],
+    pub scene_model: Mat4,
+    pub view_proj: Mat4,
+    pub light_pos: Vec4,
+    pub light_view: Mat4,
+    pub light_proj: Mat4,
+    // draw objects with one instanced call per mesh range rather than
+    // one call each
+    pub instanced: bool,
+    // draw calls and state changes made by the passes so far
+    pub stats: RefCell<QueueStats>,
+}
+
+// a pass in the render graph. inputs and outputs are named textures; the
+// graph connects each input to the output of the same name
+pub trait RenderNode {
+    fn inputs(&self) -> &[&'static str] {
+        &[]
+    }
+
+    fn outputs(&self) -> &[&'static str] {
+        &[]
+    }
+
+    fn set_input(&mut self, _slot: usize, _tex: Texture) {}
+
+    fn get_output(&self, _slot: usize) -> Option<Texture> {
+        None
+    }
+
+    fn resize(&mut self, _ctx: &mut Context, _width: f32, _height: f32) {}
+
+    fn draw(&self, gfx: &mut dyn Backend, frame: &Frame);
+}
+
+#[derive(Default)]
+pub struct RenderGraph {
+    nodes: Vec<Box<dyn RenderNode>>,
+    // for each node, the (node, output slot) feeding each of its inputs
+    links: Vec<Vec<(usize, usize)>>,
+    order: Vec<usize>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            nodes: vec![],
+            links: vec![],
+            order: vec![],
+        }
+    }
+
+    pub fn add<N: RenderNode + 'static>(&mut self, node: N) -> usize {
+        self.nodes.push(Box::new(node));
+        self.nodes.len() - 1
+    }
+
+    pub fn build(&mut self) -> Result<(), String> {
+        // find who produces each named texture
+        let mut producers = HashMap::<&'static str, (usize, usize)>::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for (slot, name) in node.outputs().iter().enumerate() {
+                if producers.insert(name, (i, slot)).is_some() {
+                    return Err(format!("output '{}' is produced twice", name));
+                }
+            }
+        }
+
+        self.links = vec![];
+        for node in self.nodes.iter() {
+            let mut links = vec![];
+            for name in node.inputs().iter() {
+                match producers.get(name) {
+                    Some(link) => links.push(*link),
+                    None => return Err(format!("no pass produces '{}'", name)),
+                }
+            }
+            self.links.push(links);
+        }
+
+        // run each pass once everything it reads has been drawn, otherwise
+        // keeping the order the passes were added in
+        self.order = vec![];
+        let mut done = vec![false; self.nodes.len()];
+        while self.order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len()).find(|i| {
+                !done[*i] && self.links[*i].iter().all(|(p, _)| done[*p])
+            });
+            match next {
+                Some(i) => {
+                    done[i] = true;
+                    self.order.push(i);
+                }
+                None => return Err("render graph has a cycle".to_string()),
+            }
+        }
+
+        for i in self.order.clone() {
+            self.bind_inputs(i);
+        }
+        Ok(())
+    }
+
+    fn bind_inputs(&mut self, i: usize) {
+        for slot in 0..self.links[i].len() {
+            let (p, output) = self.links[i][slot];
+            let tex = self.nodes[p].get_output(output)
+                .expect("pass declared an output it does not provide");
+            self.nodes[i].set_input(slot, tex);
+        }
+    }
+
+    pub fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.resize_with(|_, node| node.resize(ctx, width, height));
+    }
+
+    // resize each pass with resize, given its index, and rebind its inputs.
+    // producers come first in the order so their new targets are ready by
+    // the time each dependent pass is rebound
+    fn resize_with(&mut self, mut resize: impl FnMut(usize, &mut dyn RenderNode)) {
+        for i in self.order.clone() {
+            resize(i, self.nodes[i].as_mut());
+            self.bind_inputs(i);
+        }
+    }
+
+    pub fn draw(&self, gfx: &mut dyn Backend, frame: &Frame) {
+        for i in self.order.iter() {
+            self.nodes[*i].draw(gfx, frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    // what the stub passes have been given, and how many times each has
+    // been resized
+    #[derive(Default)]
+    struct Log {
+        resizes: HashMap<usize, u32>,
+        // (pass, input slot) to the (width, height) of the texture bound
+        bound: HashMap<(usize, usize), (u32, u32)>,
+    }
+
+    // a pass whose output textures are tagged with where they came from:
+    // width is ten times its index plus the slot, height its resize count
+    struct Stub {
+        index: usize,
+        inputs: Vec<&'static str>,
+        outputs: Vec<&'static str>,
+        log: Rc<RefCell<Log>>,
+    }
+
+    impl RenderNode for Stub {
+        fn inputs(&self) -> &[&'static str] {
+            &self.inputs
+        }
+
+        fn outputs(&self) -> &[&'static str] {
+            &self.outputs
+        }
+
+        fn set_input(&mut self, slot: usize, tex: Texture) {
+            self.log.borrow_mut().bound.insert((self.index, slot), (tex.width, tex.height));
+        }
+
+        fn get_output(&self, slot: usize) -> Option<Texture> {
+            let mut tex = Texture::empty();
+            tex.width = self.index as u32 * 10 + slot as u32;
+            tex.height = *self.log.borrow().resizes.get(&self.index).unwrap_or(&0);
+            Some(tex)
+        }
+
+        fn draw(&self, _gfx: &mut dyn Backend, _frame: &Frame) {}
+    }
+
+    fn graph(passes: &[(&[&'static str], &[&'static str])]) -> (RenderGraph, Rc<RefCell<Log>>) {
+        let log = Rc::new(RefCell::new(Log::default()));
+        let mut graph = RenderGraph::new();
+        for (index, (inputs, outputs)) in passes.iter().enumerate() {
+            graph.add(Stub {
+                index,
+                inputs: inputs.to_vec(),
+                outputs: outputs.to_vec(),
+                log: log.clone(),
+            });
+        }
+        (graph, log)
+    }
+
+    // added in the reverse of the order they have to run in, with a pass
+    // reading nothing at the end
+    fn post_chain() -> (RenderGraph, Rc<RefCell<Log>>) {
+        graph(&[
+            (&["colour", "bloom"], &[]),
+            (&["colour"], &["bloom"]),
+            (&[], &["depth", "colour"]),
+            (&[], &[]),
+        ])
+    }
+
+    #[test]
+    fn passes_run_after_what_they_read() {
+        let (mut graph, _) = post_chain();
+        graph.build().unwrap();
+        assert_eq!(graph.order, vec![2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn inputs_are_bound_to_the_output_of_the_same_name() {
+        let (mut graph, log) = post_chain();
+        graph.build().unwrap();
+        let log = log.borrow();
+        assert_eq!(log.bound.len(), 3);
+        // colour is the second output of pass 2
+        assert_eq!(log.bound[&(0, 0)], (21, 0));
+        assert_eq!(log.bound[&(0, 1)], (10, 0));
+        assert_eq!(log.bound[&(1, 0)], (21, 0));
+    }
+
+    #[test]
+    fn inputs_are_rebound_to_resized_outputs() {
+        let (mut graph, log) = post_chain();
+        graph.build().unwrap();
+        let resizing = log.clone();
+        graph.resize_with(|i, _| *resizing.borrow_mut().resizes.entry(i).or_insert(0) += 1);
+        let log = log.borrow();
+        assert!((0..4).all(|i| log.resizes[&i] == 1));
+        assert_eq!(log.bound[&(0, 0)], (21, 1));
+        assert_eq!(log.bound[&(0, 1)], (10, 1));
+        assert_eq!(log.bound[&(1, 0)], (21, 1));
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let (mut graph, _) = graph(&[
+            (&["b"], &["a"]),
+            (&["a"], &["b"]),
+        ]);
+        assert_eq!(graph.build(), Err("render graph has a cycle".to_string()));
+    }
+
+    #[test]
+    fn inputs_no_pass_produces_are_errors() {
+        let (mut graph, _) = graph(&[
+            (&[], &["colour"]),
+            (&["colour", "normals"], &[]),
+        ]);
+        assert_eq!(graph.build(), Err("no pass produces 'normals'".to_string()));
+    }
+}
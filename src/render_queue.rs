@@ -0,0 +1,200 @@
+use crate::backend::{Backend, Gl, Handles, Pod, as_bytes};
+
+// draws for one pass, collected so they can be sorted to keep pipeline and
+// binding changes down and drawn front to back so the depth test rejects
+// hidden fragments early. everything drawn so far is opaque. H is the
+// backend's handle types, miniquad's unless testing
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PipelineId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BindingsId(usize);
+
+// a pipeline and the bindings applied with it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct State {
+    pub pipeline: PipelineId,
+    pub bindings: BindingsId,
+}
+
+pub struct DrawItem<H: Handles = Gl> {
+    pub state: State,
+    pub base_element: i32,
+    pub num_elements: i32,
+    pub num_instances: i32,
+    pub uniforms: Vec<u8>,
+    // per-instance data to upload to a buffer before drawing
+    pub instances: Option<(H::Buffer, Vec<u8>)>,
+    // distance from the eye, smaller is drawn first
+    pub depth: f32,
+}
+
+impl<H: Handles> DrawItem<H> {
+    pub fn new<U: Pod>(state: State, base_element: i32, num_elements: i32,
+        uniforms: &U, depth: f32) -> DrawItem<H> {
+        DrawItem {
+            state,
+            base_element,
+            num_elements,
+            num_instances: 1,
+            uniforms: as_bytes(std::slice::from_ref(uniforms)).to_vec(),
+            instances: None,
+            depth,
+        }
+    }
+
+    pub fn with_instances<T: Pod>(self, buffer: H::Buffer, instances: &[T]) -> DrawItem<H> {
+        DrawItem {
+            num_instances: instances.len() as i32,
+            instances: Some((buffer, as_bytes(instances).to_vec())),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueStats {
+    pub draw_calls: usize,
+    pub pipeline_changes: usize,
+    pub binding_changes: usize,
+}
+
+impl QueueStats {
+    pub fn add(&mut self, other: QueueStats) {
+        self.draw_calls += other.draw_calls;
+        self.pipeline_changes += other.pipeline_changes;
+        self.binding_changes += other.binding_changes;
+    }
+}
+
+pub struct RenderQueue<H: Handles = Gl> {
+    pipelines: Vec<H::Pipeline>,
+    bindings: Vec<H::Bindings>,
+    items: Vec<DrawItem<H>>,
+}
+
+impl<H: Handles> RenderQueue<H> {
+    pub fn new() -> RenderQueue<H> {
+        RenderQueue {
+            pipelines: vec![],
+            bindings: vec![],
+            items: vec![],
+        }
+    }
+
+    // register each pipeline and set of bindings once per pass, and use
+    // the ids for every item drawn with them
+    pub fn pipeline(&mut self, pipeline: &H::Pipeline) -> PipelineId {
+        self.pipelines.push(*pipeline);
+        PipelineId(self.pipelines.len() - 1)
+    }
+
+    pub fn bindings(&mut self, bindings: &H::Bindings) -> BindingsId {
+        self.bindings.push(bindings.clone());
+        BindingsId(self.bindings.len() - 1)
+    }
+
+    pub fn state(&mut self, pipeline: &H::Pipeline, bindings: &H::Bindings) -> State {
+        State {
+            pipeline: self.pipeline(pipeline),
+            bindings: self.bindings(bindings),
+        }
+    }
+
+    // a state for each of several bindings drawn with one pipeline, such
+    // as one per page of meshes
+    pub fn states(&mut self, pipeline: PipelineId, bindings: &[H::Bindings]) -> Vec<State> {
+        bindings.iter()
+            .map(|b| State { pipeline, bindings: self.bindings(b) })
+            .collect()
+    }
+
+    pub fn submit(&mut self, item: DrawItem<H>) {
+        self.items.push(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // sort and draw everything submitted, inside the current pass
+    pub fn flush(&mut self, gfx: &mut dyn Backend<H>) -> QueueStats {
+        self.items.sort_by(|a, b| {
+            a.state.cmp(&b.state)
+                .then(a.depth.partial_cmp(&b.depth)
+                    .unwrap_or(std::cmp::Ordering::Equal))
+        });
+
+        let mut stats = QueueStats::default();
+        let mut pipeline = None;
+        let mut bindings = None;
+        for item in self.items.drain(..) {
+            let state = item.state;
+            if pipeline != Some(state.pipeline) {
+                gfx.apply_pipeline(&self.pipelines[state.pipeline.0]);
+                pipeline = Some(state.pipeline);
+                // attributes are set up for the bound pipeline
+                bindings = None;
+                stats.pipeline_changes += 1;
+            }
+            if bindings != Some(state.bindings) {
+                gfx.apply_bindings(&self.bindings[state.bindings.0]);
+                bindings = Some(state.bindings);
+                stats.binding_changes += 1;
+            }
+            if let Some((buffer, data)) = item.instances.as_ref() {
+                gfx.update_buffer(buffer, data);
+            }
+            if !item.uniforms.is_empty() {
+                gfx.apply_uniforms_from_bytes(&item.uniforms);
+            }
+            gfx.draw(item.base_element, item.num_elements, item.num_instances);
+            stats.draw_calls += 1;
+        }
+        self.pipelines.clear();
+        self.bindings.clear();
+        stats
+    }
+}
+
+impl<H: Handles> Default for RenderQueue<H> {
+    fn default() -> RenderQueue<H> {
+        RenderQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniquad::PassAction;
+    use crate::backend::{Ids, Recorder};
+
+    #[test]
+    fn flush_groups_by_state_and_draws_front_to_back() {
+        let mut queue = RenderQueue::<Ids>::new();
+        let a = queue.state(&10, &20);
+        let b = queue.state(&11, &21);
+        for (state, depth) in [(a, 3.0), (b, 1.0), (a, 1.0), (b, 2.0), (a, 2.0)].iter() {
+            queue.submit(DrawItem::new(*state, 0, 3, depth, *depth));
+        }
+        let mut gfx = Recorder::<Ids>::new();
+        gfx.begin_pass(None, PassAction::Nothing);
+        let stats = queue.flush(&mut gfx);
+        gfx.end_render_pass();
+
+        let drawn: Vec<(Option<usize>, f32)> = gfx.draws.iter()
+            .map(|d| (d.pipeline, d.uniforms_as::<f32>()))
+            .collect();
+        assert_eq!(drawn, vec![(Some(10), 1.0), (Some(10), 2.0), (Some(10), 3.0),
+            (Some(11), 1.0), (Some(11), 2.0)]);
+        assert_eq!(stats.draw_calls, 5);
+        assert_eq!(stats.pipeline_changes, 2);
+        assert_eq!(stats.binding_changes, 2);
+        assert!(queue.is_empty());
+    }
+}
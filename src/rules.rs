@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+use serde::Deserialize;
+use crate::objects;
+
+// what the world is built from, read from a toml file so it can be tuned
+// without a recompile. objects interprets it
+
+// the rules shipped with the demo, used when the file can't be read
+pub const BUILTIN: &str = include_str!("../rules.toml");
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    // in each grid cell of every loaded chunk
+    Grid,
+    // a fixed number wrapped to stay within spread of the camera
+    Scatter,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub mesh: String,
+    pub placement: Placement,
+    // grid: chance of an object in a cell outside a clearing
+    #[serde(default = "one")]
+    pub density: f32,
+    // scatter: how many, and how far either side of the camera
+    #[serde(default)]
+    pub count: usize,
+    #[serde(default = "default_spread")]
+    pub spread: f32,
+    // half width range, and half height as a multiple of the width
+    pub scale: [f32; 2],
+    #[serde(default = "one")]
+    pub height: f32,
+    // turn about the vertical in degrees
+    #[serde(default = "full_turn")]
+    pub rotation: [f32; 2],
+    // objects take colours from the palette in turn
+    #[serde(default)]
+    pub palette: Vec<[f32; 4]>,
+    #[serde(default)]
+    pub glow: bool,
+    // scatter: top speed in units a second, top turn in degrees a second,
+    // how far they bob, and how much their colour pulses
+    #[serde(default)]
+    pub drift: f32,
+    #[serde(default)]
+    pub spin: f32,
+    #[serde(default)]
+    pub bob: f32,
+    #[serde(default)]
+    pub pulse: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_spread() -> f32 {
+    10.0
+}
+
+fn full_turn() -> [f32; 2] {
+    [-180.0, 180.0]
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    // chunks loaded around the camera, and how much further they're kept
+    pub load_radius: i32,
+    pub hysteresis: i32,
+    #[serde(rename = "layer", default)]
+    pub layers: Vec<Layer>,
+}
+
+impl Rules {
+    pub fn parse(text: &str) -> Result<Rules, String> {
+        let rules: Rules = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (i, layer) in rules.layers.iter().enumerate() {
+            if objects::mesh(&layer.mesh).is_none() {
+                return Err(format!("layer {}: unknown mesh {}", i, layer.mesh));
+            }
+            if layer.scale[0] > layer.scale[1] || layer.rotation[0] > layer.rotation[1] {
+                return Err(format!("layer {}: range min is over max", i));
+            }
+            // wrap divides by the spread
+            if layer.spread <= 0.0 {
+                return Err(format!("layer {}: spread must be positive", i));
+            }
+            // a flat object has no rotation to recover from its matrix
+            if layer.scale[0] <= 0.0 || layer.height <= 0.0 {
+                return Err(format!("layer {}: scale and height must be positive", i));
+            }
+            if !(0.0..=1.0).contains(&layer.density) {
+                return Err(format!("layer {}: density must be from 0 to 1", i));
+            }
+            if layer.drift < 0.0 || layer.spin < 0.0 || layer.bob < 0.0 || layer.pulse < 0.0 {
+                return Err(format!("layer {}: drift, spin, bob and pulse can't be negative", i));
+            }
+            if layer.glow && layer.palette.is_empty() {
+                return Err(format!("layer {}: glowing layers need a palette", i));
+            }
+        }
+        if rules.load_radius < 0 || rules.hysteresis < 0 {
+            return Err("load_radius and hysteresis can't be negative".to_string());
+        }
+        Ok(rules)
+    }
+
+    pub fn builtin() -> Rules {
+        Rules::parse(BUILTIN).unwrap()
+    }
+}
+
+// a rules file on disk, looked at once a second for changes
+pub struct RulesFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: f64,
+}
+
+impl RulesFile {
+    pub fn new(path: impl Into<PathBuf>) -> RulesFile {
+        RulesFile {
+            path: path.into(),
+            modified: None,
+            next_check: 0.0,
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    // the rules if the file changed since it was last read. None when it
+    // hasn't, or can't be read at all, as on the web
+    pub fn poll(&mut self, now: f64) -> Option<Result<Rules, String>> {
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + 1.0;
+
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        let text = std::fs::read_to_string(&self.path).ok()?;
+        Some(Rules::parse(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scatter(extra: &str) -> String {
+        format!("load_radius = 1\nhysteresis = 1\n[[layer]]\nmesh = \"cube\"\n\
+            placement = \"scatter\"\ncount = 3\n{}\n", extra)
+    }
+
+    #[test]
+    fn builtin_rules_parse() {
+        Rules::builtin();
+    }
+
+    #[test]
+    fn rejects_spread_that_is_not_positive() {
+        assert!(Rules::parse(&scatter("scale = [0.1, 0.2]\nspread = 5.0")).is_ok());
+        assert!(Rules::parse(&scatter("scale = [0.1, 0.2]\nspread = 0.0")).is_err());
+        assert!(Rules::parse(&scatter("scale = [0.1, 0.2]\nspread = -1.0")).is_err());
+    }
+
+    #[test]
+    fn rejects_scale_that_is_not_positive() {
+        // every object the same size is fine
+        assert!(Rules::parse(&scatter("scale = [0.5, 0.5]")).is_ok());
+        assert!(Rules::parse(&scatter("scale = [0.0, 0.5]")).is_err());
+        assert!(Rules::parse(&scatter("scale = [-0.5, 0.5]")).is_err());
+        assert!(Rules::parse(&scatter("scale = [0.5, 0.5]\nheight = 0.0")).is_err());
+    }
+
+    #[test]
+    fn rejects_density_outside_zero_to_one() {
+        let grid = |density: &str| format!("load_radius = 1\nhysteresis = 1\n[[layer]]\n\
+            mesh = \"cube\"\nplacement = \"grid\"\nscale = [0.2, 0.3]\ndensity = {}\n",
+            density);
+        assert!(Rules::parse(&grid("0.0")).is_ok());
+        assert!(Rules::parse(&grid("1.0")).is_ok());
+        assert!(Rules::parse(&grid("-0.1")).is_err());
+        assert!(Rules::parse(&grid("1.5")).is_err());
+    }
+}
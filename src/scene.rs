@@ -0,0 +1,350 @@
+use std::path::Path;
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
+use glam::{vec3, Vec3, Vec4, Quat, Mat4, EulerRot};
+use crate::objects::{self, Object, ColouredObject, DEFAULT_SEED};
+
+// a hand-written or saved scene: fixed objects, the camera and light, and
+// which generators fill in the rest. stored as toml, whose errors give the
+// line and column
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generator {
+    // the grid layers and terrain, streamed in chunks
+    Chunks,
+    // the scatter layers' entities
+    Scatter,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Camera {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    // vertical, in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            eye: [0.5, 0.125, 5.0],
+            target: [0.5, 0.0, 0.0],
+            fov: 60.0,
+            near: 0.01,
+            far: 20.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_at_rh(Vec3::from(self.eye), Vec3::from(self.target),
+            vec3(0.0, 1.0, 0.0))
+    }
+
+    pub fn proj(&self, aspect: f32) -> Mat4 {
+        Mat4::perspective_rh_gl(self.fov.to_radians(), aspect, self.near, self.far)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Light {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    // in degrees
+    pub fov: f32,
+    // near and far of the shadow map
+    pub range: [f32; 2],
+}
+
+impl Default for Light {
+    fn default() -> Light {
+        Light {
+            position: [-100.0, 100.0, 100.0],
+            target: [0.0, 0.0, 0.0],
+            fov: 10.0,
+            range: [136.0, 200.0],
+        }
+    }
+}
+
+impl Light {
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_at_rh(Vec3::from(self.position), Vec3::from(self.target),
+            vec3(0.0, 1.0, 0.0))
+    }
+
+    pub fn proj(&self) -> Mat4 {
+        Mat4::perspective_rh_gl(self.fov.to_radians(), 1.0, self.range[0], self.range[1])
+    }
+}
+
+fn mesh_name<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    let name = String::deserialize(d)?;
+    match objects::mesh(&name) {
+        Some(_) => Ok(name),
+        None => Err(D::Error::custom(format!("unknown mesh {}", name))),
+    }
+}
+
+fn one() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+// an object placed by position, rotation in degrees about y, x and z, and
+// scale, or by a whole model matrix in column order which wins if given
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneObject {
+    #[serde(deserialize_with = "mesh_name")]
+    pub mesh: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "one")]
+    pub scale: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<[f32; 16]>,
+    // coloured objects glow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colour: Option<[f32; 4]>,
+}
+
+impl SceneObject {
+    pub fn model(&self) -> Mat4 {
+        match self.model {
+            Some(m) => Mat4::from_cols_array(&m),
+            None => {
+                let r = self.rotation;
+                Mat4::from_scale_rotation_translation(
+                    Vec3::from(self.scale),
+                    Quat::from_euler(EulerRot::YXZ, r[0].to_radians(),
+                        r[1].to_radians(), r[2].to_radians()),
+                    Vec3::from(self.position))
+            }
+        }
+    }
+
+    pub fn object(&self) -> Object {
+        Object {
+            model: self.model(),
+            ..objects::mesh(&self.mesh).unwrap()
+        }
+    }
+}
+
+fn default_seed() -> u64 {
+    DEFAULT_SEED
+}
+
+// toml integers are signed, so a bigger seed could be saved but not read
+pub const MAX_SEED: u64 = i64::MAX as u64;
+
+// a seed given on the command line
+pub fn parse_seed(text: &str) -> Result<u64, String> {
+    match text.parse::<u64>() {
+        Ok(seed) if seed <= MAX_SEED => Ok(seed),
+        _ => Err(format!("the seed needs to be a whole number from 0 to {}", MAX_SEED)),
+    }
+}
+
+// toml 0.5 puts an unknown key, and a bad value that isn't on one line, at
+// the start of its table. this finds the key within the table instead
+fn locate(text: &str, e: toml::de::Error) -> String {
+    let message = e.to_string();
+    let (line, column) = match e.line_col() {
+        Some(at) => at,
+        None => return message,
+    };
+    let key = if let Some(rest) = message.strip_prefix("unknown field `") {
+        rest.split('`').next()
+    } else {
+        message.split("for key `").nth(1)
+            .and_then(|k| k.split('`').next())
+            .and_then(|k| k.rsplit('.').next())
+    };
+    let key = match key {
+        Some(key) if column == 0 => key,
+        _ => return message,
+    };
+    for (n, l) in text.lines().enumerate().skip(line) {
+        let trimmed = l.trim_start();
+        if trimmed.starts_with('[') {
+            if n == line {
+                continue;
+            }
+            break;
+        }
+        if trimmed.starts_with(key) && trimmed[key.len()..].trim_start().starts_with('=') {
+            let before = message.split(" at line ").next().unwrap();
+            return format!("{} at line {} column {}", before, n + 1, l.len() - trimmed.len() + 1);
+        }
+    }
+    message
+}
+
+fn all_generators() -> Vec<Generator> {
+    vec![Generator::Chunks, Generator::Scatter]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+    #[serde(default = "all_generators")]
+    pub generators: Vec<Generator>,
+    // how far the world has moved past the camera
+    #[serde(default)]
+    pub offset: [f32; 3],
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default)]
+    pub light: Light,
+    #[serde(rename = "object", default)]
+    pub objects: Vec<SceneObject>,
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene {
+            seed: default_seed(),
+            generators: all_generators(),
+            offset: [0.0, 0.0, 0.0],
+            camera: Camera::default(),
+            light: Light::default(),
+            objects: vec![],
+        }
+    }
+}
+
+impl Scene {
+    pub fn parse(text: &str) -> Result<Scene, String> {
+        toml::from_str(text).map_err(|e| locate(text, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Scene, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Scene::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn runs(&self, generator: Generator) -> bool {
+        self.generators.contains(&generator)
+    }
+
+    // the fixed objects, split into plain and coloured
+    pub fn objects(&self) -> (Vec<Object>, Vec<ColouredObject>) {
+        let mut objects = Vec::<Object>::new();
+        let mut coloured_objects = Vec::<ColouredObject>::new();
+        for sobj in self.objects.iter() {
+            match sobj.colour {
+                Some(colour) => coloured_objects.push(ColouredObject {
+                    object: sobj.object(),
+                    colour: Vec4::from(colour),
+                }),
+                None => objects.push(sobj.object()),
+            }
+        }
+        (objects, coloured_objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"seed = 9223372036854775807
+generators = ["chunks"]
+offset = [1.0, 0.0, -2.5]
+
+[camera]
+eye = [0.0, 1.0, 4.0]
+target = [0.0, 0.0, 0.0]
+fov = 45.0
+near = 0.1
+far = 50.0
+
+[[object]]
+mesh = "cube"
+position = [1.0, 2.0, 3.0]
+rotation = [90.0, 0.0, 0.0]
+scale = [0.5, 0.5, 0.5]
+
+[[object]]
+mesh = "sphere"
+model = [2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 1.0, 1.0, 1.0, 1.0]
+colour = [1.0, 0.5, 0.0, 1.0]
+"#;
+
+    fn error(text: &str) -> String {
+        Scene::parse(text).err().unwrap()
+    }
+
+    #[test]
+    fn scenes_survive_saving_and_loading() {
+        let path = std::env::temp_dir()
+            .join(format!("round_trip_{}.toml", std::process::id()));
+        let scene = Scene::parse(SCENE).unwrap();
+        scene.save(&path).unwrap();
+        let loaded = Scene::load(&path);
+        let saved = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(toml::to_string(&loaded).unwrap(), saved.unwrap());
+        assert_eq!(loaded.seed, MAX_SEED);
+        assert!(loaded.runs(Generator::Chunks) && !loaded.runs(Generator::Scatter));
+        assert_eq!(loaded.offset, [1.0, 0.0, -2.5]);
+        assert_eq!(loaded.camera.fov, 45.0);
+        let (objects, coloured_objects) = loaded.objects();
+        let (expected, expected_coloured) = scene.objects();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].model, expected[0].model);
+        assert_eq!(coloured_objects.len(), 1);
+        assert_eq!(coloured_objects[0].object.model, expected_coloured[0].object.model);
+        assert_eq!(coloured_objects[0].colour, expected_coloured[0].colour);
+    }
+
+    #[test]
+    fn seeds_must_fit_in_toml() {
+        assert_eq!(parse_seed("42"), Ok(42));
+        assert_eq!(parse_seed("9223372036854775807"), Ok(MAX_SEED));
+        assert!(parse_seed("9223372036854775808").is_err());
+        assert!(parse_seed("-1").is_err());
+        assert!(parse_seed("one").is_err());
+    }
+
+    #[test]
+    fn errors_give_the_line_and_column_of_the_key() {
+        assert_eq!(error("seed = 1\n[camera]\nbogus = 1\n"),
+            "unknown field `bogus`, expected one of `eye`, `target`, `fov`, `near`, `far` \
+            for key `camera` at line 3 column 1");
+        assert_eq!(error("seed = 1\n\n[[object]]\nmesh = \"cube\"\n\n[[object]]\n\
+            mesh = \"cube\"\n  colur = [1.0, 0.0, 0.0, 1.0]\n"),
+            "unknown field `colur`, expected one of `mesh`, `position`, `rotation`, \
+            `scale`, `model`, `colour` for key `object` at line 8 column 3");
+        assert_eq!(error("seed = 1\n\nbogus = 2\n"),
+            "unknown field `bogus`, expected one of `seed`, `generators`, `offset`, \
+            `camera`, `light`, `object` at line 3 column 1");
+    }
+
+    #[test]
+    fn value_errors_keep_their_position() {
+        assert_eq!(error("seed = 1\n[camera]\nfov = \"wide\"\n"),
+            "invalid type: string \"wide\", expected f32 for key `camera.fov` \
+            at line 3 column 7");
+        assert_eq!(error("[[object]]\nmesh = \"teapot\"\n"),
+            "unknown mesh teapot for key `object.mesh` at line 2 column 1");
+    }
+}
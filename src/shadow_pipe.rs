@@ -1,37 +1,31 @@
 use miniquad::*;
-use glam::Mat4;
-use crate::objects::Object;
-use crate::blur_shadow_pipe::BlurShadowPipe;
+use mq_test::backend::{Backend, Handles, Pod};
+use mq_test::gpu::{self, OwnedPipeline};
+use mq_test::mesh::Vertex;
+use mq_test::render_queue::{DrawItem, RenderQueue, State};
+use glam::vec4;
+use crate::blur::Blur;
+use crate::blur_shadow_pipe;
+use crate::instancing::{self, Batches, InstanceBuffer};
+use crate::terrain;
+use crate::render_graph::{Frame, RenderNode};
+use crate::target::{Size, Target};
 
 pub struct ShadowPipe {
-    pass:RenderPass,
-    pipe:Pipeline,
-    blur_pipe:BlurShadowPipe,
+    target:Target,
+    pipe:OwnedPipeline,
+    instanced_pipe:OwnedPipeline,
+    binds:Vec<Bindings>,
+    instanced_binds:Vec<Bindings>,
+    instances:InstanceBuffer,
+    blur_pipe:Blur,
     output:Texture
 }
 
 impl ShadowPipe {
-    pub fn new(ctx: &mut Context) -> ShadowPipe {
-        let color_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: 512,
-                height: 512,
-                format: TextureFormat::RGBA8,
-                ..Default::default()
-            },
-        );
-        let depth_img = Texture::new_render_texture(
-            ctx,
-            TextureParams {
-                width: 512,
-                height: 512,
-                format: TextureFormat::Depth,
-                ..Default::default()
-            },
-        );
-
-        let pass = RenderPass::new(ctx, color_img, depth_img);
+    pub fn new(ctx: &mut Context, binds: Vec<Bindings>, quad: &Bindings,
+        size: Size) -> ShadowPipe {
+        let target = Target::new(ctx, "shadow_map", size, TextureFormat::RGBA8, true);
 
         let shader = Shader::new(
             ctx,
@@ -41,59 +35,116 @@ impl ShadowPipe {
         )
         .unwrap();
 
-        let pipe = Pipeline::with_params(
+        let pipe = gpu::checked_pipeline(
             ctx,
-            &[BufferLayout {
-                stride: 48,
-                ..Default::default()
-            }],
-            &[
-                VertexAttribute::new("pos", VertexFormat::Float3),
-            ],
+            VERTEX,
+            &[Vertex::layout()],
+            &Vertex::attributes(),
             shader,
             PipelineParams {
                 depth_test: Comparison::LessOrEqual,
                 depth_write: true,
                 ..Default::default()
             },
-        );
+        )
+        .unwrap();
+
+        let shader = Shader::new(
+            ctx,
+            INSTANCED_VERTEX,
+            FRAGMENT,
+            meta(),
+        )
+        .unwrap();
 
-        let blur_pipe = BlurShadowPipe::new(ctx, 2.0, color_img);
+        let instanced_pipe = instancing::pipeline(ctx, INSTANCED_VERTEX, shader)
+            .unwrap();
+        let instances = InstanceBuffer::new(ctx);
+        let instanced_binds = binds.iter().map(|b| instances.bind(b)).collect();
+
+        let blur_pipe = Blur::new(ctx, quad, size, 2.0, target.output(),
+            blur_shadow_pipe::shaders());
         let output = blur_pipe.get_output();
-        let output = color_img;
+        let output = target.output();
 
         ShadowPipe {
-            pass,
-            pipe,
+            target,
+            pipe: OwnedPipeline::new(pipe, "shadow_map"),
+            instanced_pipe: OwnedPipeline::new(instanced_pipe, "shadow_map instanced"),
+            binds,
+            instanced_binds,
+            instances,
             blur_pipe,
             output
         }
     }
 
-    pub fn draw(&self, ctx: &mut Context,
-        bind: &Bindings,
-        objects: &Vec<Object>, model: &Mat4, view: &Mat4, proj: &Mat4) {
-        ctx.begin_pass(
-            self.pass,
+}
+
+impl RenderNode for ShadowPipe {
+    fn outputs(&self) -> &[&'static str] {
+        &["shadow_map"]
+    }
+
+    fn get_output(&self, _slot: usize) -> Option<Texture> {
+        Some(self.output)
+    }
+
+    fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if self.target.resize(ctx, width, height) {
+            self.blur_pipe.set_input(self.target.output());
+            self.output = self.target.output();
+        }
+        self.blur_pipe.resize(ctx, width, height);
+    }
+
+    fn draw(&self, gfx: &mut dyn Backend, frame: &Frame) {
+        gfx.begin_pass(
+            Some(self.target.pass()),
             PassAction::clear_color(1.0, 1.0, 1.0, 1.0),
         );
-        ctx.apply_pipeline(&self.pipe);
-        ctx.apply_bindings(bind);
-        for obj in objects.iter() {
-            ctx.apply_uniforms(&Uniforms {
-                mvp: *proj * *view * *model * obj.model
-            });
-            ctx.draw(obj.start, obj.end, 1);
+        let mut queue: RenderQueue = RenderQueue::new();
+        // shared by the instanced objects and the terrain
+        let instanced = queue.pipeline(&self.instanced_pipe);
+        if frame.instanced {
+            let states = queue.states(instanced, &self.instanced_binds);
+            queue_instanced(&mut queue, &states, self.instances.buffer(), frame);
+        } else {
+            let pipeline = queue.pipeline(&self.pipe);
+            let states = queue.states(pipeline, &self.binds);
+            queue_objects(&mut queue, &states, frame);
         }
-        ctx.end_render_pass();
-        self.blur_pipe.draw(ctx);
+        terrain::queue_patches(&mut queue, instanced, &self.instanced_binds[0],
+            frame.shadow_terrain, vec4(0., 0., 0., 0.), &Uniforms {
+                mvp: frame.light_proj * frame.light_view * frame.scene_model
+            });
+        frame.stats.borrow_mut().add(queue.flush(gfx));
+        gfx.end_render_pass();
+        self.blur_pipe.draw(gfx, frame);
     }
+}
 
-    pub fn get_output(&self) -> Texture {
-        self.output
+// every object as seen from the light
+pub fn queue_objects<H: Handles>(queue: &mut RenderQueue<H>, states: &[State], frame: &Frame) {
+    let light_vp = frame.light_proj * frame.light_view * frame.scene_model;
+    for obj in frame.shadow_casters.iter() {
+        let mvp = light_vp * obj.model;
+        // clip w of the origin is its distance in front of the light
+        let range = frame.meshes.get(obj.mesh);
+        queue.submit(DrawItem::new(states[range.page], range.start, range.count,
+            &Uniforms { mvp }, mvp.w_axis.w));
     }
 }
 
+// the same with one draw per mesh range, uploading instances to buffer
+pub fn queue_instanced<H: Handles>(queue: &mut RenderQueue<H>, states: &[State],
+    buffer: H::Buffer, frame: &Frame) {
+    let batches = Batches::objects(frame.shadow_casters, vec4(0., 0., 0., 0.));
+    let light_vp = frame.light_proj * frame.light_view * frame.scene_model;
+    instancing::submit(queue, states, buffer, frame.meshes, batches, light_vp,
+        &Uniforms { mvp: light_vp });
+}
+
 pub const VERTEX: &str = r#"#version 100
 attribute vec4 pos;
 
@@ -107,6 +158,20 @@ void main() {
 }
 "#;
 
+pub const INSTANCED_VERTEX: &str = r#"#version 100
+attribute vec4 pos;
+attribute mat4 inst_model;
+
+varying vec4 vpos;
+
+uniform mat4 mvp;
+
+void main() {
+    vpos = mvp * inst_model * pos;
+    gl_Position = vpos;
+}
+"#;
+
 pub const FRAGMENT: &str = r#"#version 100
 precision mediump float;
 
@@ -143,6 +208,105 @@ pub fn meta() -> ShaderMeta {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Uniforms {
     pub mvp: glam::Mat4,
-}
\ No newline at end of file
+}
+
+unsafe impl Pod for Uniforms {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use glam::{vec3, Mat4};
+    use mq_test::backend::{as_bytes, Ids, Recorder};
+    use crate::instancing::Instance;
+    use crate::meshes::{self, MeshRegistry};
+    use crate::objects::Object;
+
+    fn casters() -> Vec<Object> {
+        ["cube", "sphere", "cube", "torus"].iter().enumerate().map(|(i, name)| Object {
+            model: Mat4::from_translation(vec3(i as f32 * 3.0, 0.0, -(i as f32)))
+                * Mat4::from_scale(vec3(1.0, 2.0, 1.0)),
+            mesh: meshes::builtin(name).unwrap(),
+        }).collect()
+    }
+
+    fn frame<'a>(meshes: &'a MeshRegistry, casters: &'a [Object]) -> Frame<'a> {
+        Frame {
+            meshes,
+            objects: &[],
+            coloured_objects: &[],
+            terrain: &[],
+            shadow_casters: casters,
+            shadow_terrain: &[],
+            scene_model: Mat4::from_rotation_y(0.3),
+            view_proj: Mat4::IDENTITY,
+            light_pos: glam::vec4(0.0, 10.0, 0.0, 1.0),
+            light_view: Mat4::look_at_rh(vec3(0.0, 10.0, 5.0), vec3(0.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0)),
+            light_proj: Mat4::orthographic_rh_gl(-10.0, 10.0, -10.0, 10.0, 0.1, 30.0),
+            instanced: false,
+            stats: RefCell::default(),
+        }
+    }
+
+    // the queue's items drawn into a recorder inside one pass
+    fn record(queue: &mut RenderQueue<Ids>) -> Recorder<Ids> {
+        let mut gfx = Recorder::<Ids>::new();
+        gfx.begin_pass(Some(0), PassAction::Nothing);
+        queue.flush(&mut gfx);
+        gfx.end_render_pass();
+        gfx
+    }
+
+    #[test]
+    fn draws_every_object_with_the_light_mvp() {
+        let meshes = MeshRegistry::new();
+        let casters = casters();
+        let frame = frame(&meshes, &casters);
+        let mut queue = RenderQueue::<Ids>::new();
+        let state = queue.state(&1, &2);
+        queue_objects(&mut queue, &[state], &frame);
+        let gfx = record(&mut queue);
+
+        let light_vp = frame.light_proj * frame.light_view * frame.scene_model;
+        assert_eq!(gfx.draws.len(), casters.len());
+        for obj in casters.iter() {
+            let range = meshes.get(obj.mesh);
+            let mvp = light_vp * obj.model;
+            let drawn = gfx.draws.iter().filter(|d| {
+                d.uniforms_as::<Uniforms>().mvp == mvp
+                    && d.base_element == range.start && d.num_elements == range.count
+            }).count();
+            assert_eq!(drawn, 1);
+        }
+        assert!(gfx.draws.iter().all(|d| d.pipeline == Some(1) && d.bindings == Some(2)));
+    }
+
+    #[test]
+    fn instanced_draws_every_object_with_the_light_mvp() {
+        let meshes = MeshRegistry::new();
+        let casters = casters();
+        let frame = frame(&meshes, &casters);
+        let mut queue = RenderQueue::<Ids>::new();
+        let state = queue.state(&1, &2);
+        queue_instanced(&mut queue, &[state], 3, &frame);
+        let gfx = record(&mut queue);
+
+        // one draw per mesh, each uploading its instances first
+        let light_vp = frame.light_proj * frame.light_view * frame.scene_model;
+        assert_eq!(gfx.draws.len(), 3);
+        assert_eq!(gfx.uploads.len(), gfx.draws.len());
+        for (draw, upload) in gfx.draws.iter().zip(gfx.uploads.iter()) {
+            assert_eq!(draw.uniforms_as::<Uniforms>().mvp, light_vp);
+            assert_eq!(upload.len(),
+                draw.num_instances as usize * std::mem::size_of::<Instance>());
+        }
+        for obj in casters.iter() {
+            let instance = Instance::new(obj.model, vec4(0., 0., 0., 0.));
+            let bytes = as_bytes(std::slice::from_ref(&instance));
+            assert!(gfx.uploads.iter().any(|u| u.chunks(bytes.len()).any(|c| c == bytes)));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use miniquad::*;
+use mq_test::gpu::{OwnedPass, OwnedTexture};
+
+// how big an offscreen target is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+    // width, height in pixels whatever the screen size
+    Fixed(u32, u32),
+    // a fraction of the screen width and height
+    Screen(f32),
+}
+
+impl Size {
+    pub fn resolve(&self, width: f32, height: f32) -> (u32, u32) {
+        match *self {
+            Size::Fixed(w, h) => (w, h),
+            Size::Screen(f) => (
+                ((width * f) as u32).max(1),
+                ((height * f) as u32).max(1),
+            ),
+        }
+    }
+}
+
+// a colour texture with optional depth, and the pass that draws to them
+pub struct Target {
+    pass:OwnedPass,
+    output:OwnedTexture,
+    depth:Option<OwnedTexture>,
+    label:&'static str,
+    format:TextureFormat,
+    size:Size,
+    width:u32,
+    height:u32,
+}
+
+fn render_texture(ctx: &mut Context, width: u32, height: u32,
+    format: TextureFormat) -> Texture {
+    Texture::new_render_texture(
+        ctx,
+        TextureParams {
+            width,
+            height,
+            format,
+            ..Default::default()
+        },
+    )
+}
+
+impl Target {
+    pub fn new(ctx: &mut Context, label: &'static str, size: Size,
+        format: TextureFormat, with_depth: bool) -> Target {
+        let (sw, sh) = ctx.screen_size();
+        Target::new_resolved(ctx, label, size, format, with_depth, sw, sh)
+    }
+
+    // recreate the textures if the screen size changes what size they
+    // should be. returns true if the output texture was replaced
+    pub fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) -> bool {
+        if self.size.resolve(width, height) == (self.width, self.height) {
+            return false;
+        }
+        // dropping the old target frees its textures and pass
+        *self = Target::new_resolved(ctx, self.label, self.size, self.format,
+            self.depth.is_some(), width, height);
+        true
+    }
+
+    fn new_resolved(ctx: &mut Context, label: &'static str, size: Size,
+        format: TextureFormat, with_depth: bool, sw: f32, sh: f32) -> Target {
+        let (width, height) = size.resolve(sw, sh);
+        let output = render_texture(ctx, width, height, format);
+        let depth = if with_depth {
+            Some(render_texture(ctx, width, height, TextureFormat::Depth))
+        } else {
+            None
+        };
+        let pass = RenderPass::new(ctx, output, depth);
+        Target {
+            pass: OwnedPass::new(pass, label),
+            output: OwnedTexture::new(output, label),
+            depth: depth.map(|d| OwnedTexture::new(d, label)),
+            label,
+            format,
+            size,
+            width,
+            height,
+        }
+    }
+
+    pub fn pass(&self) -> RenderPass {
+        *self.pass
+    }
+
+    pub fn output(&self) -> Texture {
+        *self.output
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
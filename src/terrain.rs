@@ -0,0 +1,103 @@
+use miniquad::*;
+use glam::{vec2, vec3, vec4, Vec3, Vec4, Mat4};
+use mq_test::noise;
+use mq_test::backend::Pod;
+use mq_test::gpu::OwnedBuffer;
+use mq_test::mesh::{Mesh, Vertex};
+use mq_test::render_queue::{DrawItem, PipelineId, RenderQueue, State};
+use crate::culling::Aabb;
+use crate::instancing::Instance;
+
+// rolling ground built from fbm noise, one grid patch per chunk
+
+// world units between grid vertices
+const SPACING: f32 = 0.5;
+// frequency in cycles per unit and height either side of BASE
+const TERRAIN_SCALE: f32 = 0.08;
+const HEIGHT_SCALE: f32 = 0.6;
+// where the old flat ground was
+const BASE: f32 = -1.0;
+
+pub fn height(seed: u64, x: f32, z: f32) -> f32 {
+    let n = noise::fbm(noise::perlin, seed.wrapping_add(32),
+        x * TERRAIN_SCALE, z * TERRAIN_SCALE, 4);
+    BASE + HEIGHT_SCALE * n
+}
+
+pub fn normal(seed: u64, x: f32, z: f32) -> Vec3 {
+    // central differences across one grid step
+    let e = SPACING * 0.5;
+    let dx = height(seed, x + e, z) - height(seed, x - e, z);
+    let dz = height(seed, x, z + e) - height(seed, x, z - e);
+    vec3(-dx, 2.0 * e, -dz).normalize()
+}
+
+// the grid for the square of side size with its min corner at origin
+fn patch_mesh(seed: u64, origin: Vec3, size: f32) -> Mesh {
+    let n = (size / SPACING) as u16;
+    let mut mesh = Mesh::new();
+    for j in 0..=n {
+        for i in 0..=n {
+            let x = origin.x + i as f32 * SPACING;
+            let z = origin.z + j as f32 * SPACING;
+            let nrm = normal(seed, x, z);
+            mesh.push(Vertex::new(vec3(x, height(seed, x, z), z),
+                vec4(1.0, 1.0, 1.0, 1.0), nrm,
+                vec2(i as f32 / n as f32, j as f32 / n as f32))).unwrap();
+        }
+    }
+
+    let row = n + 1;
+    for j in 0..n {
+        for i in 0..n {
+            let a = j * row + i;
+            mesh.indices.extend_from_slice(&[a, a + row, a + 1, a + 1, a + row, a + row + 1]);
+        }
+    }
+    mesh
+}
+
+pub struct TerrainPatch {
+    vertex_buffer: OwnedBuffer,
+    index_buffer: OwnedBuffer,
+    num_elements: i32,
+    pub bounds: Aabb,
+    // what was uploaded, kept for exporting
+    pub mesh: Mesh,
+}
+
+impl TerrainPatch {
+    pub fn new(ctx: &mut Context, seed: u64, origin: Vec3, size: f32) -> TerrainPatch {
+        let mesh = patch_mesh(seed, origin, size);
+        let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &mesh.vertices);
+        let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &mesh.indices);
+        TerrainPatch {
+            vertex_buffer: OwnedBuffer::new(vertex_buffer, "terrain"),
+            index_buffer: OwnedBuffer::new(index_buffer, "terrain"),
+            num_elements: mesh.indices.len() as i32,
+            bounds: Aabb::new(vec3(origin.x, mesh.min.y, origin.z),
+                vec3(origin.x + size, mesh.max.y, origin.z + size)),
+            mesh,
+        }
+    }
+}
+
+// each patch drawn once, untransformed, through an instanced pipeline so
+// it works the same whether or not the objects are instanced. pipeline is
+// the caller's instanced pipeline, already in the queue, and bind its
+// bindings, with the mesh swapped for the patch's
+pub fn queue_patches<U: Pod>(queue: &mut RenderQueue, pipeline: PipelineId,
+    bind: &Bindings, patches: &[&TerrainPatch], colour: Vec4, uniforms: &U) {
+    let instance = Instance::new(Mat4::IDENTITY, colour);
+    for patch in patches.iter() {
+        let mut patch_bind = bind.clone();
+        patch_bind.vertex_buffers[0] = *patch.vertex_buffer;
+        patch_bind.index_buffer = *patch.index_buffer;
+        let state = State {
+            pipeline,
+            bindings: queue.bindings(&patch_bind),
+        };
+        queue.submit(DrawItem::new(state, 0, patch.num_elements, uniforms, 0.0)
+            .with_instances(bind.vertex_buffers[1], &[instance]));
+    }
+}
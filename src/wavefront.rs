@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::path::Path;
+use glam::{vec2, vec4, Vec2, Vec3, Vec4};
+use crate::mesh::{Mesh, Vertex};
+
+// wavefront obj meshes, read into the layout cube_verts uses. polygons are
+// split into fans of triangles. materials, groups and anything else that
+// isn't geometry are skipped
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normals {
+    // one normal per triangle, so edges stay hard
+    Flat,
+    // the file's normals, or where it has none the triangles around each
+    // position averaged
+    Smooth,
+}
+
+// a corner of a face: indices into the positions, and the uvs and normals
+// if it gave them
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Corner {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+// obj indices count from one, or back from the end when negative
+fn index(text: &str, count: usize, what: &str) -> Result<usize, String> {
+    let i: i64 = text.parse().map_err(|_| format!("bad {} index {}", what, text))?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if i == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} out of range, there are {}", what, i, count));
+    }
+    Ok(resolved as usize)
+}
+
+fn corner(text: &str, positions: usize, uvs: usize, normals: usize) -> Result<Corner, String> {
+    let mut parts = text.split('/');
+    let v = index(parts.next().unwrap(), positions, "position")?;
+    let vt = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(index(s, uvs, "uv")?),
+    };
+    let vn = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(index(s, normals, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("bad face corner {}", text));
+    }
+    Ok(Corner { v, vt, vn })
+}
+
+fn floats(words: &[&str], min: usize, max: usize, what: &str) -> Result<Vec<f32>, String> {
+    if words.len() < min || words.len() > max {
+        return Err(format!("{} needs {} to {} numbers, not {}", what, min, max, words.len()));
+    }
+    words.iter()
+        .map(|w| w.parse::<f32>().map_err(|_| format!("bad number {} in {}", w, what)))
+        .collect()
+}
+
+// colour is used for vertices the file gives no colour of their own
+pub fn parse(text: &str, normals: Normals, colour: Vec4) -> Result<Mesh, String> {
+    let mut positions = Vec::<Vec3>::new();
+    let mut colours = Vec::<Vec4>::new();
+    let mut uvs = Vec::<Vec2>::new();
+    let mut file_normals = Vec::<Vec3>::new();
+    let mut triangles = Vec::<[Corner; 3]>::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let at = |e: String| format!("line {}: {}", n + 1, e);
+        let line = line.split('#').next().unwrap();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((k, a)) => (*k, a),
+            None => continue,
+        };
+        match keyword {
+            "v" => {
+                // x y z, an optional w, or x y z r g b as some tools write
+                let f = floats(args, 3, 7, "v").map_err(at)?;
+                positions.push(Vec3::new(f[0], f[1], f[2]));
+                colours.push(match f.len() {
+                    6 => vec4(f[3], f[4], f[5], 1.0),
+                    7 => vec4(f[3], f[4], f[5], f[6]),
+                    3 | 4 => colour,
+                    _ => return Err(at("v needs 3, 4, 6 or 7 numbers".to_string())),
+                });
+            }
+            "vt" => {
+                let f = floats(args, 1, 3, "vt").map_err(at)?;
+                uvs.push(vec2(f[0], f.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let f = floats(args, 3, 3, "vn").map_err(at)?;
+                file_normals.push(Vec3::new(f[0], f[1], f[2]));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(at(format!("a face needs 3 corners, not {}", args.len())));
+                }
+                let corners = args.iter()
+                    .map(|a| corner(a, positions.len(), uvs.len(), file_normals.len()))
+                    .collect::<Result<Vec<Corner>, String>>()
+                    .map_err(at)?;
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    if triangles.is_empty() {
+        return Err("no faces".to_string());
+    }
+
+    let face_normal = |t: &[Corner; 3]| {
+        let (a, b, c) = (positions[t[0].v], positions[t[1].v], positions[t[2].v]);
+        (b - a).cross(c - a)
+    };
+    // unnormalised face normals are weighted by area, so slivers count little
+    let mut averaged = vec![Vec3::ZERO; positions.len()];
+    if normals == Normals::Smooth {
+        for t in triangles.iter() {
+            let n = face_normal(t);
+            for c in t.iter() {
+                averaged[c.v] += n;
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new();
+    let mut seen = HashMap::<Corner, u16>::new();
+    for t in triangles.iter() {
+        let flat = face_normal(t).normalize_or_zero();
+        for c in t.iter() {
+            // flat vertices can't be shared with other triangles
+            if normals == Normals::Smooth {
+                if let Some(i) = seen.get(c) {
+                    mesh.indices.push(*i);
+                    continue;
+                }
+            }
+            let nrm = match (normals, c.vn) {
+                (Normals::Flat, _) => flat,
+                (Normals::Smooth, Some(vn)) => file_normals[vn].normalize_or_zero(),
+                (Normals::Smooth, None) => averaged[c.v].normalize_or_zero(),
+            };
+            let uv = c.vt.map_or(Vec2::ZERO, |vt| uvs[vt]);
+            let i = mesh.push(Vertex::new(positions[c.v], colours[c.v], nrm, uv))?;
+            seen.insert(*c, i);
+            mesh.indices.push(i);
+        }
+    }
+    Ok(mesh)
+}
+
+pub fn load(path: &Path, normals: Normals, colour: Vec4) -> Result<Mesh, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text, normals, colour).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vec4 = Vec4::ONE;
+
+    // two quads sharing an edge, folded along it
+    const FOLD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 2 1\nv 1 2 1\n\
+        f 1 2 3 4\nf 4 3 6 5\n";
+
+    fn error(text: &str) -> String {
+        parse(text, Normals::Flat, WHITE).err().unwrap()
+    }
+
+    #[test]
+    fn quads_are_split_into_fans() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n",
+            Normals::Smooth, WHITE).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        for v in mesh.vertices.iter() {
+            assert_eq!(v.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn flat_normals_keep_triangles_apart() {
+        let mesh = parse(FOLD, Normals::Flat, WHITE).unwrap();
+        assert_eq!(mesh.indices.len(), 12);
+        assert_eq!(mesh.vertices.len(), 12);
+        // both triangles of a planar quad face the same way
+        assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, 1.0]);
+        let bent = Vec3::from(mesh.vertices[6].normal);
+        assert!((bent - Vec3::new(0.0, -1.0, 1.0).normalize()).length() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_normals_share_vertices_across_the_fold() {
+        let mesh = parse(FOLD, Normals::Smooth, WHITE).unwrap();
+        assert_eq!(mesh.indices.len(), 12);
+        assert_eq!(mesh.vertices.len(), 6);
+        // the shared edge leans between the two faces, weighted by the
+        // area of the triangles touching it: one from the flat quad, two
+        // from the bent one
+        let edge = mesh.vertices.iter().find(|v| v.pos == [0.0, 1.0, 0.0]).unwrap();
+        let expected = Vec3::new(0.0, -2.0, 3.0).normalize();
+        assert!((Vec3::from(edge.normal) - expected).length() < 1e-6);
+    }
+
+    #[test]
+    fn file_normals_and_uvs_are_used() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.25\nvn 0 0 2\n\
+            f 1/1/1 2/1/1 3//1\n", Normals::Smooth, WHITE).unwrap();
+        assert_eq!(mesh.vertices[0].uv, [0.5, 0.25]);
+        assert_eq!(mesh.vertices[2].uv, [0.0, 0.0]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_end() {
+        let relative = parse("v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n",
+            Normals::Flat, WHITE).unwrap();
+        let absolute = parse("v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 2 3 4\n",
+            Normals::Flat, WHITE).unwrap();
+        assert_eq!(relative.vertices, absolute.vertices);
+        assert_eq!(relative.indices, absolute.indices);
+    }
+
+    #[test]
+    fn vertex_colours_override_the_default() {
+        let grey = vec4(0.5, 0.5, 0.5, 1.0);
+        let mesh = parse("v 0 0 0 1 0 0\nv 1 0 0 0 1 0 0.5\nv 0 1 0\nf 1 2 3\n",
+            Normals::Flat, grey).unwrap();
+        assert_eq!(mesh.vertices[0].colour, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(mesh.vertices[1].colour, [0.0, 1.0, 0.0, 0.5]);
+        assert_eq!(mesh.vertices[2].colour, [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn comments_and_other_statements_are_skipped() {
+        let mesh = parse("# a triangle\nmtllib a.mtl\no tri\nv 0 0 0\nv 1 0 0 # x\n\
+            v 0 1 0\ng side\nusemtl red\ns off\nf 1 2 3\n", Normals::Flat, WHITE).unwrap();
+        assert_eq!(mesh.indices.len(), 3);
+    }
+
+    #[test]
+    fn short_vertices_are_errors() {
+        assert_eq!(error("v 0 0\n"), "line 1: v needs 3 to 7 numbers, not 2");
+        assert_eq!(error("v 0 0 0 1 1\n"), "line 1: v needs 3, 4, 6 or 7 numbers");
+        assert_eq!(error("v 0 0 x\n"), "line 1: bad number x in v");
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        let tri = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        assert_eq!(error(&format!("{}f 1 2 4\n", tri)),
+            "line 4: position index 4 out of range, there are 3");
+        assert_eq!(error(&format!("{}f 1 2 -4\n", tri)),
+            "line 4: position index -4 out of range, there are 3");
+        assert_eq!(error(&format!("{}f 0 1 2\n", tri)),
+            "line 4: position index 0 out of range, there are 3");
+        assert_eq!(error(&format!("{}f 1/1 2/1 3/1\n", tri)),
+            "line 4: uv index 1 out of range, there are 0");
+        assert_eq!(error(&format!("{}f 1//1 2//1 3//1\n", tri)),
+            "line 4: normal index 1 out of range, there are 0");
+    }
+
+    #[test]
+    fn faces_need_three_corners() {
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 2\n"), "line 3: a face needs 3 corners, not 2");
+    }
+
+    #[test]
+    fn files_without_faces_are_errors() {
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nv 0 1 0\n"), "no faces");
+        assert_eq!(error(""), "no faces");
+    }
+}